@@ -0,0 +1,7 @@
+// arbitrary registers and programs, executed against a flat memory
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  rgba::fuzz::exec_program(data);
+});
@@ -0,0 +1,7 @@
+// arbitrary cartridge images, run for a bounded number of instructions
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  rgba::fuzz::run_rom(data);
+});
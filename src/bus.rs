@@ -0,0 +1,190 @@
+// connects the CPU to memory and everything else clocked along with it,
+// so that they advance on every memory access instead of per instruction.
+// the clock is advanced on every access, while everything else only runs
+// once the scheduler says it has something to do
+
+use super::gpu::GPU;
+use super::mmu::{real_mmu::RealMMU, Fault, MMU};
+use super::scheduler::{Event, Scheduler};
+
+pub struct Bus<'a> {
+  mmu: &'a mut RealMMU,
+  gpu: &'a mut GPU,
+  scheduler: &'a mut Scheduler,
+}
+
+impl<'a> Bus<'a> {
+  pub fn new(mmu: &'a mut RealMMU, gpu: &'a mut GPU, scheduler: &'a mut Scheduler) -> Bus<'a> {
+    Bus {
+      mmu,
+      gpu,
+      scheduler,
+    }
+  }
+}
+
+impl<'a> MMU for Bus<'a> {
+  fn read8<I>(&self, index: I) -> u8
+  where
+    I: Into<usize>,
+  {
+    self.mmu.read8(index)
+  }
+
+  fn read16<I>(&self, index: I) -> u16
+  where
+    I: Into<usize>,
+  {
+    self.mmu.read16(index)
+  }
+
+  fn write8<I>(&mut self, index: I, value: u8)
+  where
+    I: Into<usize>,
+  {
+    self.mmu.write8(index, value)
+  }
+
+  fn write16<I>(&mut self, index: I, value: u16)
+  where
+    I: Into<usize>,
+  {
+    self.mmu.write16(index, value)
+  }
+
+  fn set_flag<I, U>(&mut self, address: I, mask: U)
+  where
+    I: Into<usize>,
+    U: Into<u8>,
+  {
+    self.mmu.set_flag(address, mask)
+  }
+
+  fn unset_flag<I, U>(&mut self, address: I, mask: U)
+  where
+    I: Into<usize>,
+    U: Into<u8>,
+  {
+    self.mmu.unset_flag(address, mask)
+  }
+
+  fn get_flag<I, U>(&self, address: I, mask: U) -> bool
+  where
+    I: Into<usize>,
+    U: Into<u8>,
+  {
+    self.mmu.get_flag(address, mask)
+  }
+
+  fn tick(&mut self, cycles: u8) {
+    self.scheduler.advance(cycles);
+
+    while let Some((at, event)) = self.scheduler.pop_due() {
+      match event {
+        Event::Ppu => {
+          self.gpu.step(self.gpu.cycles_to_event(), self.mmu);
+
+          let next = at + self.gpu.cycles_to_event() as u64;
+          self.scheduler.schedule(next, Event::Ppu);
+        }
+      }
+    }
+  }
+
+  fn rom_bank(&self, address: u16) -> Option<u8> {
+    self.mmu.rom_bank(address)
+  }
+
+  fn peek8(&self, address: u16) -> Option<u8> {
+    self.mmu.peek8(address)
+  }
+
+  fn poke8(&mut self, address: u16, value: u8) {
+    self.mmu.poke8(address, value)
+  }
+
+  fn take_fault(&mut self) -> Option<Fault> {
+    self.mmu.take_fault()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::buffer::Buffer;
+  use crate::cpu::CPU;
+  use crate::mmu::addrs::Addr;
+  use std::sync::Arc;
+
+  fn new_gpu() -> GPU {
+    GPU::new(Arc::new(Buffer::from_size(160, 144)))
+  }
+
+  // the GPU only running on its events behaves as if stepped on every tick,
+  // down to the lines drawn so far
+  #[test]
+  fn tick_matches_lockstep() {
+    let buffer = Arc::new(Buffer::from_size(160, 144));
+    let lockstep_buffer = Arc::new(Buffer::from_size(160, 144));
+    let mut mmu = RealMMU::new(false, vec![0; 0x8000]);
+    let mut gpu = GPU::new(Arc::clone(&buffer));
+    let mut scheduler = Scheduler::new();
+    let mut lockstep_mmu = RealMMU::new(false, vec![0; 0x8000]);
+    let mut lockstep_gpu = GPU::new(Arc::clone(&lockstep_buffer));
+    scheduler.schedule(gpu.cycles_to_event() as u64, Event::Ppu);
+
+    let mut line = 0;
+
+    // a couple of frames
+    for _ in 0..2 * 70224 / 4 {
+      let mut bus = Bus::new(&mut mmu, &mut gpu, &mut scheduler);
+      bus.tick(4);
+      lockstep_gpu.step(4, &mut lockstep_mmu);
+
+      for &addr in &[Addr::CurrentScanLine as usize, Addr::InterruptFlag as usize] {
+        assert_eq!(bus.read8(addr), lockstep_mmu.read8(addr));
+      }
+
+      if bus.read8(Addr::CurrentScanLine) != line {
+        line = bus.read8(Addr::CurrentScanLine);
+        assert!(**buffer.get() == **lockstep_buffer.get(), "line {}", line);
+      }
+    }
+
+    assert_eq!(scheduler.now(), 2 * 70224);
+    assert!(**buffer.get() == **lockstep_buffer.get());
+  }
+
+  // instructions executed in a frame's worth of clock cycles
+  fn instructions_per_frame(double_speed: bool) -> usize {
+    let mut mmu = RealMMU::new(false, vec![0; 0x8000]);
+    let mut gpu = new_gpu();
+    let mut scheduler = Scheduler::new();
+    scheduler.schedule(gpu.cycles_to_event() as u64, Event::Ppu);
+
+    let mut cpu = CPU::new();
+    let mut state = cpu.snapshot();
+    state.pc = 0x0100;
+    state.double_speed = double_speed;
+    cpu.restore(&state);
+
+    let mut instructions = 0;
+
+    while scheduler.now() < 70224 {
+      let mut bus = Bus::new(&mut mmu, &mut gpu, &mut scheduler);
+      cpu.exec(&mut bus).unwrap();
+      instructions += 1;
+    }
+
+    assert_eq!(mmu.read8(Addr::CurrentScanLine), 0);
+    instructions
+  }
+
+  // the CPU runs twice as many NOPs in double speed, while frames take
+  // just as long
+  #[test]
+  fn double_speed_frame() {
+    assert_eq!(instructions_per_frame(false), 70224 / 4);
+    assert_eq!(instructions_per_frame(true), 70224 / 2);
+  }
+}
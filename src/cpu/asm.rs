@@ -0,0 +1,652 @@
+// assembles RGBDS syntax into machine code, the inverse of the decoders,
+// so that CPU tests don't need to encode instructions by hand. each line
+// holds a label, an instruction, or both, and `db` lists raw bytes
+// https://rgbds.gbdev.io/docs/gbz80.7
+
+use super::opcodes::{self, AluOp, Arg, ExtendedOpcode, JumpCondition, Opcode};
+use super::registers::{Register16, Register8};
+use std::collections::HashMap;
+
+// assembles the source as if loaded at the given address, or at $0000,
+// panicking if it doesn't assemble
+#[macro_export]
+macro_rules! asm {
+  ($source:expr) => {
+    $crate::asm!($source, 0)
+  };
+  ($source:expr, $origin:expr) => {
+    match $crate::cpu::asm::assemble($source, $origin) {
+      Ok(bytes) => bytes,
+      Err(err) => panic!("{}", err),
+    }
+  };
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Value {
+  Number(i64),
+  Label(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Operand {
+  Reg8(Register8),
+  Reg16(Register16),
+  Ptr(Register16),
+  // [hl+] and [hl-]
+  PtrInc,
+  PtrDec,
+  // [c], as in $FF00+c
+  PtrC,
+  Address(Value),
+  Value(Value),
+  SPOffset(i64),
+}
+
+// how the value of an instruction is encoded after its opcode
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Width {
+  Byte,
+  Signed,
+  // a jump relative to the next instruction
+  Relative,
+  // an address in $FF00-$FFFF, by its low byte
+  High,
+  Word,
+}
+
+// an instruction or byte of data, with its value still to be resolved
+struct Statement<'a> {
+  line: usize,
+  text: &'a str,
+  address: u16,
+  code: Vec<u8>,
+  size: u16,
+  value: Option<(Width, Value)>,
+}
+
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, String> {
+  let mut labels = HashMap::new();
+  let mut statements = Vec::new();
+  let mut address = origin;
+
+  for (index, line) in source.lines().enumerate() {
+    let line = line.split(';').next().unwrap().trim();
+    let error = |message: String| format!("{} on line {}: {}", message, index + 1, line);
+
+    let text = match split_label(line) {
+      Some((label, rest)) => {
+        if labels.insert(label.to_string(), address).is_some() {
+          return Err(error(format!("Duplicate label {}", label)));
+        }
+
+        rest
+      }
+      None => line,
+    };
+
+    if text.is_empty() {
+      continue;
+    }
+
+    for (code, size, value) in parse(text).map_err(error)? {
+      statements.push(Statement {
+        line: index,
+        text: line,
+        address,
+        code,
+        size,
+        value,
+      });
+
+      address = address.wrapping_add(size);
+    }
+  }
+
+  let mut bytes = Vec::new();
+
+  for statement in statements {
+    let line = statement.line;
+    let text = statement.text;
+    let error = |message: String| format!("{} on line {}: {}", message, line + 1, text);
+
+    let start = bytes.len();
+    bytes.extend_from_slice(&statement.code);
+
+    if let Some((width, value)) = statement.value {
+      let value = match value {
+        Value::Number(n) => n,
+        Value::Label(label) => match labels.get(&label) {
+          Some(&address) => address as i64,
+          None => return Err(error(format!("Unknown label {}", label))),
+        },
+      };
+
+      bytes.extend(encode_value(width, value, statement.address).map_err(error)?);
+    }
+
+    bytes.resize(start + statement.size as usize, 0);
+  }
+
+  Ok(bytes)
+}
+
+// the label at the start of the line, followed by whatever comes after it
+fn split_label(line: &str) -> Option<(&str, &str)> {
+  let colon = line.find(':')?;
+  let label = &line[..colon];
+
+  if !is_label(label) {
+    return None;
+  }
+
+  Some((label, line[colon..].trim_start_matches(':').trim()))
+}
+
+fn is_label(text: &str) -> bool {
+  let mut chars = text.chars();
+
+  match chars.next() {
+    Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+      chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    }
+    _ => false,
+  }
+}
+
+type Parsed = (Vec<u8>, u16, Option<(Width, Value)>);
+
+// the statements of a line without its label
+fn parse(text: &str) -> Result<Vec<Parsed>, String> {
+  let (mnemonic, rest) = match text.find(char::is_whitespace) {
+    Some(space) => (&text[..space], text[space..].trim()),
+    None => (text, ""),
+  };
+  let mnemonic = mnemonic.to_ascii_lowercase();
+  let texts: Vec<&str> = if rest.is_empty() {
+    vec![]
+  } else {
+    rest.split(',').map(str::trim).collect()
+  };
+
+  if mnemonic == "db" {
+    return texts
+      .iter()
+      .map(|text| Ok((vec![], 1, Some((Width::Byte, value(text)?)))))
+      .collect();
+  }
+
+  if let Some(opcode) = extended(&mnemonic, &texts)? {
+    let byte = (0..=255u8)
+      .find(|&byte| opcodes::decode_extended(byte) == opcode)
+      .unwrap();
+
+    return Ok(vec![(vec![0xCB, byte], 2, None)]);
+  }
+
+  let (candidates, value) = candidates(&mnemonic, &texts)?;
+  let (opcode, byte) = candidates
+    .iter()
+    .find_map(|&opcode| encode(opcode).map(|byte| (opcode, byte)))
+    .ok_or_else(|| "Invalid operands".to_string())?;
+
+  let value = match (width(opcode), value) {
+    (Some(width), Some(value)) => Some((width, value)),
+    (None, None) => None,
+    _ => return Err("Invalid operands".to_string()),
+  };
+
+  Ok(vec![(vec![byte], opcodes::op_size(opcode), value)])
+}
+
+// the byte decoding to the opcode, if there's one
+fn encode(opcode: Opcode) -> Option<u8> {
+  match opcode {
+    Opcode::CALLBACK | Opcode::ILLEGAL(_) => None,
+    _ => (0..=255u8).find(|&byte| opcodes::decode(byte) == opcode),
+  }
+}
+
+// the opcodes the mnemonic and operands could stand for, as a number
+// could be either 8 or 16 bits wide, along with the value of the operands
+fn candidates(mnemonic: &str, texts: &[&str]) -> Result<(Vec<Opcode>, Option<Value>), String> {
+  use Opcode::*;
+
+  if let Some(opcode) = no_operands(mnemonic) {
+    return match texts.len() {
+      0 => Ok((vec![opcode], None)),
+      _ => Err("Unexpected operands".to_string()),
+    };
+  }
+
+  // jumps, calls and returns may start with a condition
+  let (condition, texts) = match texts.split_first() {
+    Some((first, rest)) if ["jp", "jr", "call", "ret"].contains(&mnemonic) => {
+      match condition(first) {
+        Some(condition) => (condition, rest),
+        None => (JumpCondition::Always, texts),
+      }
+    }
+    _ => (JumpCondition::Always, texts),
+  };
+
+  let operands = texts
+    .iter()
+    .map(|text| operand(text))
+    .collect::<Result<Vec<_>, _>>()?;
+  let high = mnemonic == "ldh";
+  let args: Vec<Vec<Arg>> = operands.iter().map(|operand| args(operand, high)).collect();
+
+  let mut opcodes = Vec::new();
+
+  match (mnemonic, args.as_slice()) {
+    ("ld", [to, from]) | ("ldh", [to, from]) => {
+      for &to in to {
+        for &from in from {
+          opcodes.push(match (&operands[0], &operands[1]) {
+            (Operand::PtrInc, _) | (_, Operand::PtrInc) => LDI(to, from),
+            (Operand::PtrDec, _) | (_, Operand::PtrDec) => LDD(to, from),
+            _ => LD(to, from),
+          });
+        }
+      }
+    }
+    ("add", [to, from]) => {
+      for &to in to {
+        for &from in from {
+          opcodes.push(ADD(to, from));
+          opcodes.push(ALU(AluOp::Add, to, from));
+        }
+      }
+    }
+    (_, [from]) if alu(mnemonic).is_some() => {
+      for &from in from {
+        opcodes.push(ALU(alu(mnemonic).unwrap(), Arg::Reg8(Register8::A), from));
+      }
+    }
+    (_, [to, from]) if alu(mnemonic).is_some() => {
+      for &to in to {
+        for &from in from {
+          opcodes.push(ALU(alu(mnemonic).unwrap(), to, from));
+        }
+      }
+    }
+    ("inc", [arg]) => opcodes.extend(arg.iter().map(|&arg| INC(arg))),
+    ("dec", [arg]) => opcodes.extend(arg.iter().map(|&arg| DEC(arg))),
+    ("jr", [_]) => opcodes.push(JUMP(condition, Arg::Imm8)),
+    ("jp", [target]) => match target.as_slice() {
+      [Arg::Reg16(reg16)] => opcodes.push(JUMP(condition, Arg::Reg16(*reg16))),
+      _ => opcodes.push(JUMP(condition, Arg::Addr16)),
+    },
+    ("call", [_]) => opcodes.push(CALL(condition, Arg::Addr16)),
+    ("ret", []) => opcodes.push(RET(condition)),
+    ("push", [_]) | ("pop", [_]) => {
+      if let Operand::Reg16(reg16) = operands[0] {
+        opcodes.push(if mnemonic == "push" {
+          PUSH(reg16)
+        } else {
+          POP(reg16)
+        });
+      }
+    }
+    ("rst", [_]) => match operands[0] {
+      Operand::Value(Value::Number(n @ 0..=0x38)) if n % 8 == 0 => {
+        return Ok((vec![RST((n / 8) as u8)], None));
+      }
+      _ => return Err("Invalid restart vector".to_string()),
+    },
+    _ => return Err("Unknown instruction".to_string()),
+  }
+
+  let value = operands.into_iter().find_map(|operand| match operand {
+    Operand::Address(value) | Operand::Value(value) => Some(value),
+    Operand::SPOffset(n) => Some(Value::Number(n)),
+    _ => None,
+  });
+
+  Ok((opcodes, value))
+}
+
+fn no_operands(mnemonic: &str) -> Option<Opcode> {
+  use Opcode::*;
+
+  match mnemonic {
+    "nop" => Some(NOP),
+    "rlca" => Some(RLCA),
+    "rrca" => Some(RRCA),
+    "rla" => Some(RLA),
+    "rra" => Some(RRA),
+    "stop" => Some(STOP),
+    "daa" => Some(DAA),
+    "cpl" => Some(CPL),
+    "scf" => Some(SCF),
+    "ccf" => Some(CCF),
+    "halt" => Some(HALT),
+    "reti" => Some(RETI),
+    "di" => Some(DI),
+    "ei" => Some(EI),
+    _ => None,
+  }
+}
+
+fn extended(mnemonic: &str, texts: &[&str]) -> Result<Option<ExtendedOpcode>, String> {
+  use ExtendedOpcode::*;
+
+  let arg = |text: &str| match operand(text)? {
+    Operand::Reg8(reg8) => Ok(Arg::Reg8(reg8)),
+    Operand::Ptr(Register16::HL) => Ok(Arg::PtrReg16(Register16::HL)),
+    _ => Err("Invalid operands".to_string()),
+  };
+
+  let opcode = match (mnemonic, texts) {
+    ("rlc", [text]) => RLC(arg(text)?),
+    ("rrc", [text]) => RRC(arg(text)?),
+    ("rl", [text]) => RL(arg(text)?),
+    ("rr", [text]) => RR(arg(text)?),
+    ("sla", [text]) => SLA(arg(text)?),
+    ("sra", [text]) => SRA(arg(text)?),
+    ("swap", [text]) => SWAP(arg(text)?),
+    ("srl", [text]) => SRL(arg(text)?),
+    ("bit", [n, text]) | ("res", [n, text]) | ("set", [n, text]) => {
+      let n = match number(n) {
+        Some(n @ 0..=7) => n as u8,
+        _ => return Err("Invalid bit".to_string()),
+      };
+
+      match mnemonic {
+        "bit" => BIT(n, arg(text)?),
+        "res" => RES(n, arg(text)?),
+        _ => SET(n, arg(text)?),
+      }
+    }
+    _ => return Ok(None),
+  };
+
+  Ok(Some(opcode))
+}
+
+// the args the operand could be decoded from, with addresses in brackets
+// being in high memory for ldh
+fn args(operand: &Operand, high: bool) -> Vec<Arg> {
+  match operand {
+    Operand::Reg8(reg8) => vec![Arg::Reg8(*reg8)],
+    Operand::Reg16(reg16) => vec![Arg::Reg16(*reg16)],
+    Operand::Ptr(reg16) => vec![Arg::PtrReg16(*reg16)],
+    Operand::PtrInc | Operand::PtrDec => vec![Arg::PtrReg16(Register16::HL)],
+    Operand::PtrC => vec![Arg::HighMemReg8(Register8::C)],
+    Operand::Address(_) if high => vec![Arg::HighMemImm8],
+    Operand::Address(_) => vec![Arg::Addr16],
+    Operand::Value(_) => vec![Arg::Imm8, Arg::Imm16],
+    Operand::SPOffset(_) => vec![Arg::SPPlusImm8],
+  }
+}
+
+fn width(opcode: Opcode) -> Option<Width> {
+  use Arg::*;
+  use Opcode::*;
+
+  match opcode {
+    JUMP(_, Imm8) => Some(Width::Relative),
+    ADD(_, Imm8) | LD(_, SPPlusImm8) => Some(Width::Signed),
+    LD(HighMemImm8, _) | LD(_, HighMemImm8) => Some(Width::High),
+    LD(_, Imm8) | ALU(_, _, Imm8) => Some(Width::Byte),
+    LD(_, Imm16) | LD(Addr16, _) | LD(_, Addr16) | JUMP(_, Addr16) | CALL(_, Addr16) => {
+      Some(Width::Word)
+    }
+    _ => None,
+  }
+}
+
+fn encode_value(width: Width, value: i64, address: u16) -> Result<Vec<u8>, String> {
+  let in_range = |min: i64, max: i64| {
+    if (min..=max).contains(&value) {
+      Ok(value)
+    } else {
+      Err(format!("Value {} out of range", value))
+    }
+  };
+
+  let bytes = match width {
+    Width::Byte => vec![in_range(-0x80, 0xFF)? as u8],
+    Width::Signed => vec![in_range(-0x80, 0x7F)? as u8],
+    Width::Relative => {
+      let offset = value - address as i64 - 2;
+
+      if !(-0x80..=0x7F).contains(&offset) {
+        return Err(format!("Jump to ${:04X} out of range", value));
+      }
+
+      vec![offset as u8]
+    }
+    Width::High => match value {
+      0x00..=0xFF | 0xFF00..=0xFFFF => vec![value as u8],
+      _ => return Err(format!("Address ${:04X} not in high memory", value)),
+    },
+    Width::Word => (in_range(-0x8000, 0xFFFF)? as u16).to_le_bytes().to_vec(),
+  };
+
+  Ok(bytes)
+}
+
+fn operand(text: &str) -> Result<Operand, String> {
+  let lower = text.to_ascii_lowercase();
+
+  if lower.starts_with('[') && lower.ends_with(']') {
+    let inner: String = lower[1..lower.len() - 1]
+      .chars()
+      .filter(|c| !c.is_whitespace())
+      .collect();
+
+    return Ok(match inner.as_str() {
+      "hl+" | "hli" => Operand::PtrInc,
+      "hl-" | "hld" => Operand::PtrDec,
+      "c" | "$ff00+c" => Operand::PtrC,
+      _ => match reg16(&inner) {
+        Some(reg16) => Operand::Ptr(reg16),
+        None => Operand::Address(value(text[1..text.len() - 1].trim())?),
+      },
+    });
+  }
+
+  if let Some(reg8) = reg8(&lower) {
+    return Ok(Operand::Reg8(reg8));
+  }
+
+  if let Some(reg16) = reg16(&lower) {
+    return Ok(Operand::Reg16(reg16));
+  }
+
+  let offset: String = lower.chars().filter(|c| !c.is_whitespace()).collect();
+
+  if offset.starts_with("sp+") || offset.starts_with("sp-") {
+    return match number(&offset[2..]) {
+      Some(n) => Ok(Operand::SPOffset(n)),
+      None => Err(format!("Invalid offset {}", text)),
+    };
+  }
+
+  Ok(Operand::Value(value(text)?))
+}
+
+fn value(text: &str) -> Result<Value, String> {
+  if let Some(n) = number(text) {
+    Ok(Value::Number(n))
+  } else if is_label(text) {
+    Ok(Value::Label(text.to_string()))
+  } else {
+    Err(format!("Invalid operand {}", text))
+  }
+}
+
+// a number in decimal, or in hex or binary with RGBDS prefixes
+fn number(text: &str) -> Option<i64> {
+  let (negative, text) = match text.strip_prefix('-') {
+    Some(text) => (true, text),
+    None => (false, text.strip_prefix('+').unwrap_or(text)),
+  };
+
+  let n = if let Some(hex) = text.strip_prefix('$') {
+    i64::from_str_radix(hex, 16).ok()?
+  } else if let Some(binary) = text.strip_prefix('%') {
+    i64::from_str_radix(binary, 2).ok()?
+  } else {
+    text.parse::<i64>().ok()?
+  };
+
+  Some(if negative { -n } else { n })
+}
+
+fn reg8(text: &str) -> Option<Register8> {
+  use Register8::*;
+
+  match text {
+    "a" => Some(A),
+    "b" => Some(B),
+    "c" => Some(C),
+    "d" => Some(D),
+    "e" => Some(E),
+    "h" => Some(H),
+    "l" => Some(L),
+    _ => None,
+  }
+}
+
+fn reg16(text: &str) -> Option<Register16> {
+  use Register16::*;
+
+  match text {
+    "bc" => Some(BC),
+    "de" => Some(DE),
+    "hl" => Some(HL),
+    "sp" => Some(SP),
+    "af" => Some(AF),
+    _ => None,
+  }
+}
+
+fn condition(text: &str) -> Option<JumpCondition> {
+  use JumpCondition::*;
+
+  match text.to_ascii_lowercase().as_str() {
+    "nz" => Some(NotZero),
+    "z" => Some(Zero),
+    "nc" => Some(NotCarry),
+    "c" => Some(Carry),
+    _ => None,
+  }
+}
+
+fn alu(mnemonic: &str) -> Option<AluOp> {
+  use AluOp::*;
+
+  match mnemonic {
+    "add" => Some(Add),
+    "adc" => Some(Adc),
+    "sub" => Some(Sub),
+    "sbc" => Some(Sbc),
+    "and" => Some(And),
+    "xor" => Some(Xor),
+    "or" => Some(Or),
+    "cp" => Some(Cp),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cpu::disasm::disassemble_slice;
+
+  #[test]
+  fn assemble_instructions() {
+    assert_eq!(asm!("ld a, 5\n add a, b"), vec![0x3E, 0x05, 0x80]);
+    assert_eq!(asm!("ld hl, $C000"), vec![0x21, 0x00, 0xC0]);
+    assert_eq!(asm!("LD [HL+], A"), vec![0x22]);
+    assert_eq!(asm!("ldh a, [$FF44]"), vec![0xF0, 0x44]);
+    assert_eq!(asm!("ld a, [$FF44]"), vec![0xFA, 0x44, 0xFF]);
+    assert_eq!(asm!("ld [$ff00+c], a"), vec![0xE2]);
+    assert_eq!(asm!("cp %10010000\n sub b"), vec![0xFE, 0x90, 0x90]);
+    assert_eq!(asm!("add sp, -4"), vec![0xE8, 0xFC]);
+    assert_eq!(asm!("ld hl, sp - 2"), vec![0xF8, 0xFE]);
+    assert_eq!(asm!("jp c, $0150\n jp hl"), vec![0xDA, 0x50, 0x01, 0xE9]);
+    assert_eq!(asm!("ret c\n rst $38"), vec![0xD8, 0xFF]);
+    assert_eq!(asm!("bit 7, h\n swap [hl]"), vec![0xCB, 0x7C, 0xCB, 0x36]);
+    assert_eq!(
+      asm!("stop\n db $DD, 1, -1"),
+      vec![0x10, 0x00, 0xDD, 0x01, 0xFF]
+    );
+  }
+
+  #[test]
+  fn assemble_labels() {
+    let source = "
+      start:
+        ld a, [data] ; comments are skipped
+      .loop: dec a
+        jr nz, .loop
+        call start
+        jr done
+      data: db 3
+      done:
+    ";
+
+    assert_eq!(
+      asm!(source, 0xC000),
+      vec![0xFA, 0x0B, 0xC0, 0x3D, 0x20, 0xFD, 0xCD, 0x00, 0xC0, 0x18, 0x01, 0x03]
+    );
+  }
+
+  #[test]
+  fn assemble_errors() {
+    let error = |source: &str| assemble(source, 0).unwrap_err();
+
+    assert_eq!(error("nop\n foo a"), "Unknown instruction on line 2: foo a");
+    assert_eq!(error("ld a, sp"), "Invalid operands on line 1: ld a, sp");
+    assert_eq!(error("jp [hl]"), "Invalid operands on line 1: jp [hl]");
+    assert_eq!(
+      error("jp nowhere"),
+      "Unknown label nowhere on line 1: jp nowhere"
+    );
+    assert_eq!(error("a:\na:"), "Duplicate label a on line 2: a:");
+    assert_eq!(
+      error("ld a, 256"),
+      "Value 256 out of range on line 1: ld a, 256"
+    );
+    assert_eq!(error("rst 3"), "Invalid restart vector on line 1: rst 3");
+    assert_eq!(
+      error("jr $0100"),
+      "Jump to $0100 out of range on line 1: jr $0100"
+    );
+  }
+
+  // every opcode assembles back from its disassembly
+  #[test]
+  fn round_trip() {
+    for &operands in &[[0x00, 0x00], [0x12, 0x34], [0x80, 0xFF], [0xFF, 0x7F]] {
+      for byte in 0..=255u8 {
+        let mut bytes = vec![byte, operands[0], operands[1]];
+
+        // the byte after STOP isn't part of the disassembly
+        if byte == 0x10 {
+          bytes[1] = 0x00;
+        }
+
+        let instruction = disassemble_slice(&bytes, 0x0100);
+        let assembled = assemble(&instruction.text, 0x0100);
+
+        assert_eq!(assembled, Ok(instruction.bytes), "{}", instruction.text);
+      }
+    }
+
+    for byte in 0..=255u8 {
+      let instruction = disassemble_slice(&[0xCB, byte], 0x0100);
+
+      assert_eq!(
+        assemble(&instruction.text, 0x0100),
+        Ok(vec![0xCB, byte]),
+        "{}",
+        instruction.text
+      );
+    }
+  }
+}
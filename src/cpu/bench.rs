@@ -0,0 +1,167 @@
+// interpreter throughput benchmarks, comparing the matching decoders
+// against the precomputed decode table, and running with and without the
+// block cache. run them with
+//   cargo test --release bench -- --ignored --nocapture
+
+use super::opcodes::{self, DecodeTable, Opcode};
+use super::CPU;
+use crate::mmu::MMU;
+use std::time::Instant;
+
+const INSTRUCTIONS: u32 = 20_000_000;
+
+// a small loop mixing loads, ALU, CB-prefixed ops and a relative jump
+const PROGRAM: [u8; 19] = [
+  0x21, 0x00, 0xC0, // ld hl, $C000
+  0x06, 0x00, // ld b, $00
+  0x2A, // ld a, [hl+]
+  0x80, // add a, b
+  0x47, // ld b, a
+  0xEE, 0x55, // xor a, $55
+  0xCB, 0x37, // swap a
+  0x0C, // inc c
+  0x7C, // ld a, h
+  0xE6, 0xC1, // and a, $C1
+  0x67, // ld h, a
+  0x18, 0xF2, // jr $0005
+];
+
+// flat memory, so that the benchmark isn't dominated by memory accesses
+struct FlatMMU {
+  mem: Vec<u8>,
+}
+
+impl MMU for FlatMMU {
+  fn read8<I>(&self, idx: I) -> u8
+  where
+    I: Into<usize>,
+  {
+    self.mem[idx.into()]
+  }
+
+  fn read16<I>(&self, idx: I) -> u16
+  where
+    I: Into<usize>,
+  {
+    let index: usize = idx.into();
+
+    ((self.read8(index + 1) as u16) << 8) | (self.read8(index) as u16)
+  }
+
+  fn write8<I>(&mut self, idx: I, value: u8)
+  where
+    I: Into<usize>,
+  {
+    self.mem[idx.into()] = value;
+  }
+
+  fn write16<I>(&mut self, idx: I, value: u16)
+  where
+    I: Into<usize>,
+  {
+    let index: usize = idx.into();
+
+    self.write8(index, (value & 0x00FF) as u8);
+    self.write8(index + 1, ((value & 0xFF00) >> 8) as u8);
+  }
+
+  fn set_flag<I, U>(&mut self, addr: I, mask: U)
+  where
+    I: Into<usize>,
+    U: Into<u8>,
+  {
+    let address = addr.into();
+
+    self.mem[address] |= mask.into();
+  }
+
+  fn unset_flag<I, U>(&mut self, addr: I, mask: U)
+  where
+    I: Into<usize>,
+    U: Into<u8>,
+  {
+    let address = addr.into();
+
+    self.mem[address] &= !mask.into();
+  }
+
+  fn get_flag<I, U>(&self, addr: I, mask: U) -> bool
+  where
+    I: Into<usize>,
+    U: Into<u8>,
+  {
+    (self.read8(addr.into()) & mask.into()) > 0
+  }
+
+  fn peek8(&self, address: u16) -> Option<u8> {
+    Some(self.mem[address as usize])
+  }
+}
+
+// runs the program, decoding every instruction with `decode`,
+// and returns the number of instructions executed per second
+fn instructions_per_second<F: Fn(u8) -> (Opcode, u16)>(decode: F) -> f64 {
+  let mut cpu = CPU::new();
+  let mut mmu = FlatMMU {
+    mem: vec![0; 0x10000],
+  };
+  mmu.mem[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+
+  let start = Instant::now();
+
+  for _ in 0..INSTRUCTIONS {
+    let pc = cpu.regs.pc();
+    let (opcode, size) = decode(mmu.read8(pc as usize));
+    let (jump_to, _) = cpu.exec_opcode(opcode, pc, &mut mmu).unwrap();
+
+    cpu.regs.set_pc(jump_to.unwrap_or(pc + size));
+  }
+
+  INSTRUCTIONS as f64 / start.elapsed().as_secs_f64()
+}
+
+#[test]
+#[ignore]
+fn bench_decode() {
+  let table = DecodeTable::new();
+
+  let before = instructions_per_second(|byte| {
+    let opcode = opcodes::decode(byte);
+    (opcode, opcodes::op_size(opcode))
+  });
+  let after = instructions_per_second(|byte| table.decode(byte));
+
+  println!("decode:       {:>12.0} instructions/s", before);
+  println!("decode table: {:>12.0} instructions/s", after);
+  println!("speedup:      {:>12.2}x", after / before);
+}
+
+// runs the program through `exec`, with or without the block cache, and
+// returns the number of instructions executed per second
+fn exec_per_second(cache_blocks: bool) -> f64 {
+  let mut cpu = CPU::new();
+  cpu.set_block_cache(cache_blocks);
+  let mut mmu = FlatMMU {
+    mem: vec![0; 0x10000],
+  };
+  mmu.mem[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+
+  let start = Instant::now();
+
+  for _ in 0..INSTRUCTIONS {
+    cpu.exec(&mut mmu).unwrap();
+  }
+
+  INSTRUCTIONS as f64 / start.elapsed().as_secs_f64()
+}
+
+#[test]
+#[ignore]
+fn bench_block_cache() {
+  let before = exec_per_second(false);
+  let after = exec_per_second(true);
+
+  println!("exec:         {:>12.0} instructions/s", before);
+  println!("block cache:  {:>12.0} instructions/s", after);
+  println!("speedup:      {:>12.2}x", after / before);
+}
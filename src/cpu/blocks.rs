@@ -0,0 +1,416 @@
+// cache of decoded basic blocks: straight-line runs of instructions up to
+// the first one that may jump, keyed by the ROM bank and address they start
+// at. instructions executed from the cache run from the bytes they were
+// decoded from, which are neither read nor decoded again, but still take
+// the same cycles and are logged the same way
+//
+// writing to memory a block was decoded from drops the block. so does
+// writing to the registers that map other memory in, as the bank of RAM
+// or the boot ROM isn't part of the key, and writing to ROM drops blocks in
+// cartridge RAM, whose bank it may switch
+
+use super::opcodes::{DecodeTable, Opcode};
+use crate::mmu::{addrs::Addr, MMU};
+
+// instructions in a block at most, and the bytes they can take up
+const MAX_INSTRUCTIONS: usize = 32;
+const MAX_BYTES: usize = MAX_INSTRUCTIONS * 3;
+
+// instructions decoded before the cache starts over, as dropped blocks
+// aren't reclaimed until then
+const MAX_DECODED: usize = 1 << 20;
+
+const CARTRIDGE_RAM: std::ops::RangeInclusive<u16> = 0xA000..=0xBFFF;
+
+const MAPPING_REGISTERS: [u16; 3] = [
+  Addr::VRAMBank as u16,
+  Addr::BootROM as u16,
+  Addr::WRAMBank as u16,
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Instruction {
+  pub address: u16,
+  pub opcode: Opcode,
+  pub size: u16,
+  pub bytes: [u8; 3],
+}
+
+impl Instruction {
+  // the byte of the instruction at the address, if it's part of it
+  pub fn byte(&self, address: u16) -> Option<u8> {
+    let offset = address.wrapping_sub(self.address);
+
+    if offset < self.size {
+      Some(self.bytes[offset as usize])
+    } else {
+      None
+    }
+  }
+}
+
+// a block, along with the ROM bank it was decoded from
+struct Block {
+  bank: Option<u8>,
+  start: usize,
+  len: usize,
+}
+
+pub struct BlockCache {
+  decoder: DecodeTable,
+  // the block starting at each address. a block decoded from another bank
+  // at the same address replaces it
+  blocks: Vec<Option<Block>>,
+  len: usize,
+  // the instructions of every block decoded, in order
+  decoded: Vec<Instruction>,
+  // blocks decoded from each address, and from cartridge RAM in all
+  coverage: Vec<u16>,
+  cartridge_ram: usize,
+  // the instruction expected next, and the end of the block being run
+  next: usize,
+  end: usize,
+}
+
+impl BlockCache {
+  pub fn new() -> BlockCache {
+    BlockCache {
+      decoder: DecodeTable::new(),
+      blocks: (0..0x10000).map(|_| None).collect(),
+      len: 0,
+      decoded: Vec::new(),
+      coverage: vec![0; 0x10000],
+      cartridge_ram: 0,
+      next: 0,
+      end: 0,
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  // the instruction at the address, decoding the block starting there when
+  // it isn't the next one in the current block. None if it can't be cached
+  #[inline]
+  pub fn instruction<M: MMU>(&mut self, mmu: &M, address: u16) -> Option<Instruction> {
+    if self.next < self.end {
+      let instruction = self.decoded[self.next];
+
+      if instruction.address == address {
+        self.next += 1;
+        return Some(instruction);
+      }
+    }
+
+    self.enter_block(mmu, address)
+  }
+
+  // the first instruction of the block starting at the address, which
+  // becomes the current one
+  #[inline(never)]
+  fn enter_block<M: MMU>(&mut self, mmu: &M, address: u16) -> Option<Instruction> {
+    let bank = mmu.rom_bank(address);
+
+    let (start, len) = match &self.blocks[address as usize] {
+      Some(block) if block.bank == bank => (block.start, block.len),
+      _ => {
+        self.remove(address);
+
+        let instructions = self.decode(mmu, bank, address);
+
+        if instructions.is_empty() {
+          self.end = 0;
+          return None;
+        }
+
+        if self.decoded.len() + instructions.len() > MAX_DECODED {
+          self.clear();
+        }
+
+        let (start, len) = (self.decoded.len(), instructions.len());
+        self.decoded.extend(instructions);
+        self.cover(start, len, true);
+        self.blocks[address as usize] = Some(Block { bank, start, len });
+        self.len += 1;
+
+        (start, len)
+      }
+    };
+
+    self.next = start + 1;
+    self.end = start + len;
+
+    Some(self.decoded[start])
+  }
+
+  // called before every write, dropping the blocks it would change
+  pub fn will_write(&mut self, address: u16) {
+    // ROM banks are switched by writing to ROM, after which the current
+    // block may carry on in a different bank
+    if address < 0x8000 {
+      self.end = 0;
+
+      if self.cartridge_ram > 0 {
+        for address in CARTRIDGE_RAM {
+          if self.coverage[address as usize] > 0 {
+            self.invalidate(address);
+          }
+        }
+      }
+    }
+
+    if MAPPING_REGISTERS.contains(&address) {
+      self.clear();
+    } else if self.coverage[address as usize] > 0 {
+      self.invalidate(address);
+    }
+  }
+
+  pub fn clear(&mut self) {
+    for block in self.blocks.iter_mut() {
+      *block = None;
+    }
+
+    for count in self.coverage.iter_mut() {
+      *count = 0;
+    }
+
+    self.len = 0;
+    self.decoded.clear();
+    self.cartridge_ram = 0;
+    self.end = 0;
+  }
+
+  // drops the blocks covering the address, which can only start so far
+  // before it
+  fn invalidate(&mut self, address: u16) {
+    let first = address.saturating_sub(MAX_BYTES as u16 - 1);
+
+    for start in first..=address {
+      let covers = match &self.blocks[start as usize] {
+        Some(block) => self.decoded[block.start..block.start + block.len]
+          .iter()
+          .any(|i| i.byte(address).is_some()),
+        None => false,
+      };
+
+      if covers {
+        self.remove(start);
+      }
+    }
+
+    self.end = 0;
+  }
+
+  fn remove(&mut self, start: u16) {
+    if let Some(block) = self.blocks[start as usize].take() {
+      self.cover(block.start, block.len, false);
+      self.len -= 1;
+    }
+  }
+
+  fn cover(&mut self, start: usize, len: usize, covered: bool) {
+    for index in start..start + len {
+      let instruction = self.decoded[index];
+
+      for offset in 0..instruction.size {
+        let address = instruction.address.wrapping_add(offset);
+        let count = &mut self.coverage[address as usize];
+
+        if covered {
+          *count += 1;
+        } else {
+          *count -= 1;
+        }
+
+        if CARTRIDGE_RAM.contains(&address) {
+          if covered {
+            self.cartridge_ram += 1;
+          } else {
+            self.cartridge_ram -= 1;
+          }
+        }
+      }
+    }
+  }
+
+  fn decode<M: MMU>(&self, mmu: &M, bank: Option<u8>, start: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut address = start;
+
+    while instructions.len() < MAX_INSTRUCTIONS {
+      let instruction = match self.decode_instruction(mmu, bank, address) {
+        Some(instruction) => instruction,
+        None => break,
+      };
+
+      instructions.push(instruction);
+      address = address.wrapping_add(instruction.size);
+
+      if ends_block(instruction.opcode) {
+        break;
+      }
+    }
+
+    instructions
+  }
+
+  // the instruction at the address, if all of its bytes can be cached
+  fn decode_instruction<M: MMU>(
+    &self,
+    mmu: &M,
+    bank: Option<u8>,
+    address: u16,
+  ) -> Option<Instruction> {
+    let byte = |offset: u16| {
+      let address = address.wrapping_add(offset);
+
+      if cacheable(address) && mmu.rom_bank(address) == bank {
+        mmu.peek8(address)
+      } else {
+        None
+      }
+    };
+
+    let mut bytes = [byte(0)?, 0, 0];
+    let (opcode, size) = self.decoder.decode(bytes[0]);
+
+    for offset in 1..size {
+      bytes[offset as usize] = byte(offset)?;
+    }
+
+    Some(Instruction {
+      address,
+      opcode,
+      size,
+      bytes,
+    })
+  }
+}
+
+impl Default for BlockCache {
+  fn default() -> BlockCache {
+    BlockCache::new()
+  }
+}
+
+// instructions after which execution may not carry on with the next one
+fn ends_block(opcode: Opcode) -> bool {
+  matches!(
+    opcode,
+    Opcode::JUMP(..)
+      | Opcode::CALL(..)
+      | Opcode::RET(_)
+      | Opcode::RETI
+      | Opcode::RST(_)
+      | Opcode::HALT
+      | Opcode::STOP
+      | Opcode::ILLEGAL(_)
+  )
+}
+
+// OAM and the I/O registers can change without the CPU writing to them
+fn cacheable(address: u16) -> bool {
+  !matches!(address, 0xFE00..=0xFF7F | 0xFFFF)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cpu::opcodes::Arg;
+  use crate::cpu::registers::Register8;
+  use crate::mmu::test_mmu::TestMMU;
+
+  fn load(program: &[u8], at: u16) -> TestMMU {
+    let mut mmu = TestMMU::new();
+
+    for (i, &byte) in program.iter().enumerate() {
+      mmu.write8(at as usize + i, byte);
+    }
+
+    mmu
+  }
+
+  // ld a, $01 / inc a / jr $C000 / nop
+  const PROGRAM: [u8; 6] = [0x3E, 0x01, 0x3C, 0x18, 0xFB, 0x00];
+
+  #[test]
+  fn decode_block() {
+    let mmu = load(&PROGRAM, 0xC000);
+    let mut cache = BlockCache::new();
+
+    let first = cache.instruction(&mmu, 0xC000).unwrap();
+    assert_eq!(first.bytes, [0x3E, 0x01, 0x00]);
+    assert_eq!(first.size, 2);
+    assert_eq!(
+      cache.instruction(&mmu, 0xC002).unwrap().opcode,
+      Opcode::INC(Arg::Reg8(Register8::A))
+    );
+    assert_eq!(
+      cache.instruction(&mmu, 0xC003).unwrap().byte(0xC004),
+      Some(0xFB)
+    );
+
+    // the block ends at the jump
+    assert_eq!(cache.len(), 1);
+    cache.instruction(&mmu, 0xC005);
+    assert_eq!(cache.len(), 2);
+  }
+
+  #[test]
+  fn invalidate_on_write() {
+    let mut mmu = load(&PROGRAM, 0xC000);
+    let mut cache = BlockCache::new();
+    cache.instruction(&mmu, 0xC000);
+    cache.instruction(&mmu, 0xC002);
+
+    cache.will_write(0xC006);
+    assert_eq!(cache.len(), 1);
+
+    mmu.write8(0xC001u16, 0x05);
+    cache.will_write(0xC001);
+    assert!(cache.is_empty());
+
+    assert_eq!(cache.instruction(&mmu, 0xC000).unwrap().bytes[1], 0x05);
+  }
+
+  #[test]
+  fn mapping_registers() {
+    let mmu = load(&PROGRAM, 0x0100);
+    let mut cache = BlockCache::new();
+    cache.instruction(&mmu, 0x0100);
+
+    cache.will_write(Addr::BootROM as u16);
+    assert!(cache.is_empty());
+  }
+
+  #[test]
+  fn cartridge_ram_bank_switch() {
+    let mut mmu = TestMMU::new();
+    mmu.write8(0xA000u16, 0x00);
+    let mut cache = BlockCache::new();
+    cache.instruction(&mmu, 0xA000);
+    cache.instruction(&mmu, 0xC000);
+
+    cache.will_write(0x4000);
+    assert_eq!(cache.len(), 1);
+    assert!(cache.blocks[0xA000].is_none());
+  }
+
+  #[test]
+  fn uncacheable() {
+    let mmu = load(&[0x00, 0x3E], 0xFFFD);
+    let mut cache = BlockCache::new();
+
+    assert!(cache.instruction(&mmu, 0xFF40).is_none());
+
+    // stops short of LD A, d8 running into IE
+    assert_eq!(cache.instruction(&mmu, 0xFFFD).map(|i| i.size), Some(1));
+    assert!(cache.instruction(&mmu, 0xFFFE).is_none());
+  }
+}
@@ -0,0 +1,317 @@
+// shadow call stack, following CALL, RST and interrupts in and RET and RETI
+// back out, to print backtraces with
+//
+// games are free to move SP around, pop return addresses themselves or
+// return through a pushed address, so frames are matched against the stack
+// slot their return address was pushed to rather than simply popped: a
+// return from a slot drops the frame pushed there along with every frame
+// deeper down, while returning through an address pushed within a routine
+// leaves the routine's own frame in place
+//
+// while recording, every change to the frames is kept so that the journal
+// can undo them without holding a copy of the whole stack
+
+use crate::debug::symbols::Symbols;
+use std::io::{self, Write};
+
+// frames kept at most, so that a game which never returns can't grow
+// the stack forever
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FrameKind {
+  Call,
+  Rst,
+  Interrupt,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Frame {
+  pub kind: FrameKind,
+  // address of the calling instruction, or PC when the interrupt fired
+  pub caller: u16,
+  // ROM bank the caller lies in, if it's in ROM
+  pub caller_bank: Option<u8>,
+  pub target: u16,
+  // stack slot the return address was pushed to
+  pub sp: u16,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Change {
+  Pushed,
+  Popped(Frame),
+  // the outermost frame, dropped to stay within MAX_DEPTH
+  Dropped(Frame),
+}
+
+#[derive(Clone)]
+pub struct CallStack {
+  frames: Vec<Frame>,
+  // changes since they were last taken, while recording
+  changes: Option<Vec<Change>>,
+}
+
+impl CallStack {
+  pub fn new() -> CallStack {
+    CallStack {
+      frames: Vec::new(),
+      changes: None,
+    }
+  }
+
+  // frames at or below the new one's slot were left behind by SP moving up
+  pub fn push(&mut self, frame: Frame) {
+    self.unwind(frame.sp);
+
+    if self.frames.len() == MAX_DEPTH {
+      let dropped = self.frames.remove(0);
+      self.record(Change::Dropped(dropped));
+    }
+
+    self.frames.push(frame);
+    self.record(Change::Pushed);
+  }
+
+  // a return popping its address from the given stack slot
+  pub fn ret(&mut self, sp: u16) {
+    self.unwind(sp);
+  }
+
+  pub fn clear(&mut self) {
+    while let Some(frame) = self.frames.pop() {
+      self.record(Change::Popped(frame));
+    }
+  }
+
+  pub fn record_changes(&mut self, enabled: bool) {
+    self.changes = if enabled { Some(Vec::new()) } else { None };
+  }
+
+  // the changes recorded since the last call
+  pub fn take_changes(&mut self) -> Vec<Change> {
+    match self.changes.as_mut() {
+      Some(changes) => std::mem::take(changes),
+      None => Vec::new(),
+    }
+  }
+
+  // reverts the changes, the most recent last
+  pub fn undo(&mut self, changes: &[Change]) {
+    for change in changes.iter().rev() {
+      match *change {
+        Change::Pushed => {
+          self.frames.pop();
+        }
+        Change::Popped(frame) => self.frames.push(frame),
+        Change::Dropped(frame) => self.frames.insert(0, frame),
+      }
+    }
+  }
+
+  fn record(&mut self, change: Change) {
+    if let Some(changes) = self.changes.as_mut() {
+      changes.push(change);
+    }
+  }
+
+  // innermost frame last
+  pub fn frames(&self) -> &[Frame] {
+    &self.frames
+  }
+
+  fn unwind(&mut self, sp: u16) {
+    while let Some(&frame) = self.frames.last() {
+      if frame.sp > sp {
+        break;
+      }
+
+      self.frames.pop();
+      self.record(Change::Popped(frame));
+    }
+  }
+
+  // writes the frames innermost first, starting with the current PC
+  pub fn write_backtrace<W: Write>(
+    &self,
+    out: &mut W,
+    pc: u16,
+    bank: Option<u8>,
+    symbols: Option<&Symbols>,
+  ) -> io::Result<()> {
+    write_line(out, 0, pc, bank, symbols, "")?;
+
+    for (depth, frame) in self.frames.iter().rev().enumerate() {
+      let note = match frame.kind {
+        FrameKind::Call => "",
+        FrameKind::Rst => " (rst)",
+        FrameKind::Interrupt => " (interrupted)",
+      };
+
+      write_line(
+        out,
+        depth + 1,
+        frame.caller,
+        frame.caller_bank,
+        symbols,
+        note,
+      )?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Default for CallStack {
+  fn default() -> CallStack {
+    CallStack::new()
+  }
+}
+
+fn write_line<W: Write>(
+  out: &mut W,
+  depth: usize,
+  address: u16,
+  bank: Option<u8>,
+  symbols: Option<&Symbols>,
+  note: &str,
+) -> io::Result<()> {
+  match bank {
+    Some(bank) => write!(out, "#{:<3} {:02X}:{:04X}", depth, bank, address)?,
+    None => write!(out, "#{:<3} --:{:04X}", depth, address)?,
+  }
+
+  if let Some(label) = symbols.and_then(|symbols| symbols.describe(bank, address)) {
+    write!(out, " {}", label)?;
+  }
+
+  writeln!(out, "{}", note)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn frame(kind: FrameKind, caller: u16, target: u16, sp: u16) -> Frame {
+    Frame {
+      kind,
+      caller,
+      caller_bank: Some(0),
+      target,
+      sp,
+    }
+  }
+
+  fn targets(stack: &CallStack) -> Vec<u16> {
+    stack.frames().iter().map(|frame| frame.target).collect()
+  }
+
+  #[test]
+  fn push_ret() {
+    let mut stack = CallStack::new();
+    stack.push(frame(FrameKind::Call, 0x0150, 0x0200, 0xFFFC));
+    stack.push(frame(FrameKind::Rst, 0x0203, 0x0038, 0xFFFA));
+    assert_eq!(targets(&stack), [0x0200, 0x0038]);
+
+    stack.ret(0xFFFA);
+    assert_eq!(targets(&stack), [0x0200]);
+
+    stack.ret(0xFFFC);
+    assert!(stack.frames().is_empty());
+  }
+
+  #[test]
+  fn ret_through_pushed_address() {
+    let mut stack = CallStack::new();
+    stack.push(frame(FrameKind::Call, 0x0150, 0x0200, 0xFFFC));
+
+    // PUSH HL; RET as a jump, from within the routine
+    stack.ret(0xFFFA);
+    assert_eq!(targets(&stack), [0x0200]);
+  }
+
+  #[test]
+  fn sp_moved() {
+    let mut stack = CallStack::new();
+    stack.push(frame(FrameKind::Call, 0x0150, 0x0200, 0xFFFC));
+    stack.push(frame(FrameKind::Call, 0x0203, 0x0300, 0xFFFA));
+    stack.push(frame(FrameKind::Call, 0x0303, 0x0400, 0xFFF8));
+
+    // the innermost routine resets SP to return straight to the outermost
+    stack.ret(0xFFFC);
+    assert!(stack.frames().is_empty());
+
+    // SP reset above the frames, such as when restarting the main loop
+    stack.push(frame(FrameKind::Call, 0x0150, 0x0200, 0xDFFC));
+    stack.push(frame(FrameKind::Call, 0x0150, 0x0500, 0xFFFC));
+    assert_eq!(targets(&stack), [0x0500]);
+  }
+
+  #[test]
+  fn max_depth() {
+    let mut stack = CallStack::new();
+
+    for i in 0..MAX_DEPTH as u16 + 2 {
+      stack.push(frame(FrameKind::Call, 0x0150, i, 0xFFFC - i * 2));
+    }
+
+    assert_eq!(stack.frames().len(), MAX_DEPTH);
+    assert_eq!(stack.frames()[0].target, 2);
+  }
+
+  #[test]
+  fn undo() {
+    let mut stack = CallStack::new();
+    stack.push(frame(FrameKind::Call, 0x0150, 0x0200, 0xFFFC));
+    stack.push(frame(FrameKind::Call, 0x0203, 0x0300, 0xFFFA));
+    stack.record_changes(true);
+
+    stack.ret(0xFFFC);
+    stack.push(frame(FrameKind::Rst, 0x0160, 0x0038, 0xFFFC));
+    let changes = stack.take_changes();
+    assert_eq!(targets(&stack), [0x0038]);
+
+    stack.undo(&changes);
+    assert_eq!(targets(&stack), [0x0200, 0x0300]);
+    assert!(stack.take_changes().is_empty());
+
+    // the outermost frame comes back after being dropped at MAX_DEPTH
+    for i in 0..MAX_DEPTH as u16 - 2 {
+      stack.push(frame(FrameKind::Call, 0x0150, i, 0xFFF8 - i * 2));
+    }
+    stack.take_changes();
+    stack.push(frame(FrameKind::Call, 0x0150, 0x0400, 0xC000));
+    let changes = stack.take_changes();
+    assert_eq!(stack.frames()[0].target, 0x0300);
+
+    stack.undo(&changes);
+    assert_eq!(stack.frames().len(), MAX_DEPTH);
+    assert_eq!(stack.frames()[0].target, 0x0200);
+  }
+
+  #[test]
+  fn backtrace() {
+    let mut stack = CallStack::new();
+    stack.push(frame(FrameKind::Call, 0x0153, 0x0200, 0xFFFC));
+    stack.push(Frame {
+      kind: FrameKind::Interrupt,
+      caller: 0xC010,
+      caller_bank: None,
+      target: 0x0040,
+      sp: 0xFFFA,
+    });
+
+    let symbols = Symbols::parse("00:0150 Main\n00:0040 VBlank\n").unwrap();
+    let mut out = Vec::new();
+    stack
+      .write_backtrace(&mut out, 0x0042, Some(0), Some(&symbols))
+      .unwrap();
+
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "#0   00:0042 VBlank+2\n\
+       #1   --:C010 (interrupted)\n\
+       #2   00:0153 Main+3\n"
+    );
+  }
+}
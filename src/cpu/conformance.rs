@@ -0,0 +1,202 @@
+// per-opcode conformance suite, running the SingleStepTests sm83 fixtures
+// https://github.com/SingleStepTests/sm83
+//
+// the fixtures are read from $SM83_TESTS, or cache/sm83/v1 by default. when
+// they aren't there, the suite is skipped so that it still runs offline
+
+use super::CPU;
+use crate::mmu::{test_mmu::TestMMU, MMU};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const DEFAULT_DIR: &str = "cache/sm83/v1";
+
+// failing cases listed in the report, out of all of them
+const MAX_REPORTED: usize = 50;
+
+fn fixtures_dir() -> PathBuf {
+  match env::var_os("SM83_TESTS") {
+    Some(dir) => PathBuf::from(dir),
+    None => PathBuf::from(DEFAULT_DIR),
+  }
+}
+
+fn number(state: &Value, key: &str) -> u16 {
+  state[key].as_u64().unwrap() as u16
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+  state["ram"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|entry| {
+      (
+        entry[0].as_u64().unwrap() as u16,
+        entry[1].as_u64().unwrap() as u8,
+      )
+    })
+    .collect()
+}
+
+fn set_state(cpu: &mut CPU, mmu: &mut TestMMU, state: &Value) {
+  let pair = |hi: &str, lo: &str| (number(state, hi) << 8) | number(state, lo);
+
+  cpu.regs.set_af(pair("a", "f"));
+  cpu.regs.set_bc(pair("b", "c"));
+  cpu.regs.set_de(pair("d", "e"));
+  cpu.regs.set_hl(pair("h", "l"));
+  cpu.regs.set_sp(number(state, "sp"));
+  cpu.regs.set_pc(number(state, "pc"));
+  cpu.ime = number(state, "ime") > 0;
+
+  if let Some(ie) = state["ie"].as_u64() {
+    mmu.write8(0xFFFFusize, ie as u8);
+  }
+
+  for (address, value) in ram(state) {
+    mmu.write8(address as usize, value);
+  }
+}
+
+// executes a single instruction from the initial state,
+// describing every difference from the final one
+fn run_case(case: &Value) -> Result<(), String> {
+  let (initial, expected) = (&case["initial"], &case["final"]);
+  let mut cpu = CPU::new();
+  let mut mmu = TestMMU::new();
+
+  set_state(&mut cpu, &mut mmu, initial);
+
+  let step = cpu.exec(&mut mmu).map_err(|err| err.to_string())?;
+
+  let regs = [
+    ("a", cpu.regs.a() as u16),
+    ("f", cpu.regs.af() & 0x00FF),
+    ("b", cpu.regs.b() as u16),
+    ("c", cpu.regs.c() as u16),
+    ("d", cpu.regs.d() as u16),
+    ("e", cpu.regs.e() as u16),
+    ("h", cpu.regs.h() as u16),
+    ("l", cpu.regs.l() as u16),
+    ("sp", cpu.regs.sp()),
+    ("pc", cpu.regs.pc()),
+    ("ime", cpu.ime as u16),
+  ];
+
+  let mut diffs: Vec<String> = regs
+    .iter()
+    .filter(|(name, actual)| *actual != number(expected, name))
+    .map(|(name, actual)| {
+      format!(
+        "{}={:#x} expected {:#x}",
+        name,
+        actual,
+        number(expected, name)
+      )
+    })
+    .collect();
+
+  for (address, value) in ram(expected) {
+    let actual = mmu.read8(address as usize);
+
+    if actual != value {
+      diffs.push(format!(
+        "[{:#06x}]={:#04x} expected {:#04x}",
+        address, actual, value
+      ));
+    }
+  }
+
+  let cycles = case["cycles"].as_array().unwrap().len() * 4;
+
+  if step.cycles as usize != cycles {
+    diffs.push(format!("cycles={} expected {}", step.cycles, cycles));
+  }
+
+  if diffs.is_empty() {
+    Ok(())
+  } else {
+    Err(diffs.join(", "))
+  }
+}
+
+#[test]
+fn sm83_fixtures() {
+  let dir = fixtures_dir();
+
+  let mut paths: Vec<PathBuf> = match fs::read_dir(&dir) {
+    Ok(entries) => entries
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .filter(|path| path.extension() == Some("json".as_ref()))
+      .collect(),
+    Err(_) => {
+      println!("Skipping, no fixtures in {}", dir.display());
+      return;
+    }
+  };
+  paths.sort();
+
+  let mut total = 0;
+  let mut failures = Vec::new();
+
+  for path in paths {
+    let cases: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+    for case in cases.as_array().unwrap() {
+      total += 1;
+
+      if let Err(diff) = run_case(case) {
+        failures.push(format!(
+          "{}: {}",
+          case["name"].as_str().unwrap_or("?"),
+          diff
+        ));
+      }
+    }
+  }
+
+  let reported = usize::min(failures.len(), MAX_REPORTED);
+
+  assert!(
+    failures.is_empty(),
+    "{} of {} cases failed:\n{}",
+    failures.len(),
+    total,
+    failures[..reported].join("\n")
+  );
+}
+
+#[test]
+fn sm83_case() {
+  // LD (HL), B, laid out as in the fixtures
+  let case: Value = serde_json::from_str(
+    r#"{
+      "name": "70 0000",
+      "initial": {
+        "a": 1, "b": 66, "c": 3, "d": 4, "e": 5, "f": 176, "h": 192, "l": 16,
+        "pc": 256, "sp": 65534, "ime": 0, "ie": 0,
+        "ram": [[256, 112], [49168, 0]]
+      },
+      "final": {
+        "a": 1, "b": 66, "c": 3, "d": 4, "e": 5, "f": 176, "h": 192, "l": 16,
+        "pc": 257, "sp": 65534, "ime": 0,
+        "ram": [[256, 112], [49168, 66]]
+      },
+      "cycles": [[256, 112, "r-m"], [49168, 66, "-wm"]]
+    }"#,
+  )
+  .unwrap();
+
+  assert_eq!(run_case(&case), Ok(()));
+
+  let mut wrong = case.clone();
+  wrong["final"]["ram"][1][1] = Value::from(0);
+  assert_eq!(
+    run_case(&wrong),
+    Err("[0xc010]=0x42 expected 0x00".to_string())
+  );
+}
@@ -0,0 +1,407 @@
+// disassembles instructions into RGBDS syntax
+// https://rgbds.gbdev.io/docs/gbz80.7
+
+use super::opcodes::{self, AluOp, Arg, ExtendedOpcode, JumpCondition, Opcode};
+use super::registers::{Register16, Register8};
+use crate::mmu::MMU;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Instruction {
+  pub address: u16,
+  pub bytes: Vec<u8>,
+  pub text: String,
+  // the address jumped or called to, for jumps and calls to a constant
+  pub target: Option<u16>,
+}
+
+impl Instruction {
+  pub fn size(&self) -> u16 {
+    self.bytes.len() as u16
+  }
+}
+
+impl fmt::Display for Instruction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.text)
+  }
+}
+
+// disassembles the instruction at the given address of live memory,
+// without raising faults. unmapped bytes read as $FF
+pub fn disassemble<M: MMU>(mmu: &M, address: u16) -> Instruction {
+  disassemble_with(
+    |offset| mmu.peek8(address.wrapping_add(offset)).unwrap_or(0xFF),
+    address,
+  )
+}
+
+// disassembles the instruction at the start of a raw byte slice,
+// assuming it is mapped at the given address.
+// bytes past the end of the slice are read as 0
+pub fn disassemble_slice(bytes: &[u8], address: u16) -> Instruction {
+  disassemble_with(
+    |offset| bytes.get(offset as usize).cloned().unwrap_or(0),
+    address,
+  )
+}
+
+// disassembles an instruction at the given address, reading its bytes
+// through `read`, which takes the offset from the start of the instruction
+pub fn disassemble_with<F: Fn(u16) -> u8>(read: F, address: u16) -> Instruction {
+  let opcode = opcodes::decode(read(0));
+  let size = opcodes::op_size(opcode);
+  let bytes: Vec<u8> = (0..size).map(&read).collect();
+
+  let operands = Operands::new(address, &bytes);
+
+  let text = match opcode {
+    Opcode::CALLBACK => extended(opcodes::decode_extended(bytes[1])),
+    _ => operands.opcode(opcode),
+  };
+
+  let target = match opcode {
+    Opcode::JUMP(_, arg) | Opcode::CALL(_, arg) => operands.target_address(arg),
+    _ => None,
+  };
+
+  Instruction {
+    address,
+    bytes,
+    text,
+    target,
+  }
+}
+
+struct Operands<'a> {
+  address: u16,
+  bytes: &'a [u8],
+}
+
+impl<'a> Operands<'a> {
+  fn new(address: u16, bytes: &'a [u8]) -> Operands<'a> {
+    Operands { address, bytes }
+  }
+
+  fn imm8(&self) -> u8 {
+    self.bytes[1]
+  }
+
+  fn imm16(&self) -> u16 {
+    ((self.bytes[2] as u16) << 8) | (self.bytes[1] as u16)
+  }
+
+  fn signed8(&self) -> i8 {
+    self.imm8() as i8
+  }
+
+  fn arg(&self, arg: Arg) -> String {
+    use Arg::*;
+
+    match arg {
+      Addr16 => format!("[${:04X}]", self.imm16()),
+      Imm8 => format!("${:02X}", self.imm8()),
+      Imm16 => format!("${:04X}", self.imm16()),
+      PtrReg16(reg16) => format!("[{}]", reg16_name(reg16)),
+      Reg8(reg8) => reg8_name(reg8).to_string(),
+      Reg16(reg16) => reg16_name(reg16).to_string(),
+      SPPlusImm8 => format!("sp{:+}", self.signed8()),
+      HighMemImm8 => format!("[$FF{:02X}]", self.imm8()),
+      HighMemReg8(reg8) => format!("[{}]", reg8_name(reg8)),
+    }
+  }
+
+  // jump targets are not memory accesses, so they go without brackets
+  fn target(&self, arg: Arg) -> String {
+    match self.target_address(arg) {
+      Some(address) => format!("${:04X}", address),
+      None => self.arg(arg),
+    }
+  }
+
+  fn target_address(&self, arg: Arg) -> Option<u16> {
+    match arg {
+      Arg::Imm8 => {
+        let next = self.address.wrapping_add(2);
+        Some(next.wrapping_add(self.signed8() as u16))
+      }
+      Arg::Addr16 => Some(self.imm16()),
+      _ => None,
+    }
+  }
+
+  fn opcode(&self, opcode: Opcode) -> String {
+    use Arg::*;
+    use Opcode::*;
+
+    match opcode {
+      NOP => "nop".to_string(),
+      LD(HighMemImm8, from) => format!("ldh {}, {}", self.arg(HighMemImm8), self.arg(from)),
+      LD(to, HighMemImm8) => format!("ldh {}, {}", self.arg(to), self.arg(HighMemImm8)),
+      LD(HighMemReg8(reg8), from) => {
+        format!("ldh {}, {}", self.arg(HighMemReg8(reg8)), self.arg(from))
+      }
+      LD(to, HighMemReg8(reg8)) => format!("ldh {}, {}", self.arg(to), self.arg(HighMemReg8(reg8))),
+      LD(to, from) => format!("ld {}, {}", self.arg(to), self.arg(from)),
+      ADD(Reg16(Register16::SP), Imm8) => format!("add sp, {}", self.signed8()),
+      ADD(to, from) => format!("add {}, {}", self.arg(to), self.arg(from)),
+      INC(arg) => format!("inc {}", self.arg(arg)),
+      DEC(arg) => format!("dec {}", self.arg(arg)),
+      RLCA => "rlca".to_string(),
+      RRCA => "rrca".to_string(),
+      RLA => "rla".to_string(),
+      RRA => "rra".to_string(),
+      STOP => "stop".to_string(),
+      JUMP(condition, target) => {
+        let mnemonic = if target == Imm8 { "jr" } else { "jp" };
+        with_condition(mnemonic, condition, &self.target(target))
+      }
+      LDI(PtrReg16(_), from) => format!("ld [hl+], {}", self.arg(from)),
+      LDI(to, _) => format!("ld {}, [hl+]", self.arg(to)),
+      LDD(PtrReg16(_), from) => format!("ld [hl-], {}", self.arg(from)),
+      LDD(to, _) => format!("ld {}, [hl-]", self.arg(to)),
+      DAA => "daa".to_string(),
+      CPL => "cpl".to_string(),
+      SCF => "scf".to_string(),
+      CCF => "ccf".to_string(),
+      HALT => "halt".to_string(),
+      ALU(op, to, from) => format!("{} {}, {}", alu_name(op), self.arg(to), self.arg(from)),
+      POP(reg16) => format!("pop {}", reg16_name(reg16)),
+      PUSH(reg16) => format!("push {}", reg16_name(reg16)),
+      RST(n) => format!("rst ${:02X}", (n as u16) << 3),
+      RET(JumpCondition::Always) => "ret".to_string(),
+      RET(condition) => format!("ret {}", condition_name(condition)),
+      RETI => "reti".to_string(),
+      CALL(condition, target) => with_condition("call", condition, &self.target(target)),
+      DI => "di".to_string(),
+      EI => "ei".to_string(),
+      CALLBACK => unreachable!(),
+      ILLEGAL(byte) => format!("db ${:02X}", byte),
+    }
+  }
+}
+
+fn extended(opcode: ExtendedOpcode) -> String {
+  use ExtendedOpcode::*;
+
+  let operands = Operands::new(0, &[]);
+
+  match opcode {
+    RLC(arg) => format!("rlc {}", operands.arg(arg)),
+    RRC(arg) => format!("rrc {}", operands.arg(arg)),
+    RL(arg) => format!("rl {}", operands.arg(arg)),
+    RR(arg) => format!("rr {}", operands.arg(arg)),
+    SLA(arg) => format!("sla {}", operands.arg(arg)),
+    SRA(arg) => format!("sra {}", operands.arg(arg)),
+    SWAP(arg) => format!("swap {}", operands.arg(arg)),
+    SRL(arg) => format!("srl {}", operands.arg(arg)),
+    BIT(n, arg) => format!("bit {}, {}", n, operands.arg(arg)),
+    RES(n, arg) => format!("res {}, {}", n, operands.arg(arg)),
+    SET(n, arg) => format!("set {}, {}", n, operands.arg(arg)),
+  }
+}
+
+fn with_condition(mnemonic: &str, condition: JumpCondition, target: &str) -> String {
+  match condition {
+    JumpCondition::Always => format!("{} {}", mnemonic, target),
+    _ => format!("{} {}, {}", mnemonic, condition_name(condition), target),
+  }
+}
+
+pub fn reg8_name(reg: Register8) -> &'static str {
+  use Register8::*;
+
+  match reg {
+    A => "a",
+    B => "b",
+    C => "c",
+    D => "d",
+    E => "e",
+    H => "h",
+    L => "l",
+  }
+}
+
+pub fn reg16_name(reg: Register16) -> &'static str {
+  use Register16::*;
+
+  match reg {
+    BC => "bc",
+    DE => "de",
+    HL => "hl",
+    SP => "sp",
+    PC => "pc",
+    AF => "af",
+  }
+}
+
+pub fn condition_name(condition: JumpCondition) -> &'static str {
+  use JumpCondition::*;
+
+  match condition {
+    Always => "",
+    NotZero => "nz",
+    Zero => "z",
+    NotCarry => "nc",
+    Carry => "c",
+  }
+}
+
+pub fn alu_name(op: AluOp) -> &'static str {
+  use AluOp::*;
+
+  match op {
+    Add => "add",
+    Adc => "adc",
+    Sub => "sub",
+    Sbc => "sbc",
+    And => "and",
+    Xor => "xor",
+    Or => "or",
+    Cp => "cp",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mmu::{real_mmu::RealMMU, test_mmu::TestMMU};
+  use std::collections::HashMap;
+
+  macro_rules! assert_disasm {
+    ($bytes:expr, $expectation:expr) => {{
+      assert_eq!(disassemble_slice(&$bytes, 0x0100).text, $expectation);
+    }};
+  }
+
+  #[test]
+  fn disasm_no_operands() {
+    assert_disasm!([0x00], "nop");
+    assert_disasm!([0x76], "halt");
+    assert_disasm!([0x10, 0x00], "stop");
+    assert_disasm!([0xD9], "reti");
+  }
+
+  #[test]
+  fn disasm_loads() {
+    assert_disasm!([0x2A], "ld a, [hl+]");
+    assert_disasm!([0x32], "ld [hl-], a");
+    assert_disasm!([0x3E, 0x05], "ld a, $05");
+    assert_disasm!([0x21, 0x34, 0x12], "ld hl, $1234");
+    assert_disasm!([0x08, 0x34, 0x12], "ld [$1234], sp");
+    assert_disasm!([0xEA, 0x00, 0xC0], "ld [$C000], a");
+    assert_disasm!([0x36, 0xFF], "ld [hl], $FF");
+    assert_disasm!([0x0A], "ld a, [bc]");
+    assert_disasm!([0xF9], "ld sp, hl");
+    assert_disasm!([0xF8, 0xFE], "ld hl, sp-2");
+    assert_disasm!([0xF8, 0x02], "ld hl, sp+2");
+    assert_disasm!([0xE0, 0x44], "ldh [$FF44], a");
+    assert_disasm!([0xF0, 0x44], "ldh a, [$FF44]");
+    assert_disasm!([0xE2], "ldh [c], a");
+    assert_disasm!([0xF2], "ldh a, [c]");
+  }
+
+  #[test]
+  fn disasm_arithmetic() {
+    assert_disasm!([0x80], "add a, b");
+    assert_disasm!([0x9E], "sbc a, [hl]");
+    assert_disasm!([0xFE, 0x90], "cp a, $90");
+    assert_disasm!([0x09], "add hl, bc");
+    assert_disasm!([0xE8, 0xFC], "add sp, -4");
+    assert_disasm!([0x34], "inc [hl]");
+    assert_disasm!([0x1B], "dec de");
+  }
+
+  #[test]
+  fn disasm_jumps() {
+    assert_disasm!([0x20, 0x4E], "jr nz, $0150");
+    assert_disasm!([0x18, 0xFE], "jr $0100");
+    assert_disasm!([0xC3, 0x50, 0x01], "jp $0150");
+    assert_disasm!([0xDA, 0x50, 0x01], "jp c, $0150");
+    assert_disasm!([0xE9], "jp hl");
+    assert_disasm!([0xCD, 0x00, 0x40], "call $4000");
+    assert_disasm!([0xC4, 0x00, 0x40], "call nz, $4000");
+    assert_disasm!([0xC9], "ret");
+    assert_disasm!([0xD0], "ret nc");
+    assert_disasm!([0xFF], "rst $38");
+  }
+
+  #[test]
+  fn disasm_extended() {
+    assert_disasm!([0xCB, 0x7C], "bit 7, h");
+    assert_disasm!([0xCB, 0x86], "res 0, [hl]");
+    assert_disasm!([0xCB, 0xFF], "set 7, a");
+    assert_disasm!([0xCB, 0x37], "swap a");
+    assert_disasm!([0xCB, 0x11], "rl c");
+  }
+
+  #[test]
+  fn disasm_illegal() {
+    assert_disasm!([0xDD], "db $DD");
+  }
+
+  #[test]
+  fn disasm_mmu() {
+    let mut mmu = TestMMU::new();
+    mmu.write8(0xC000u16, 0xCD);
+    mmu.write16(0xC001u16, 0x1234);
+
+    let instruction = disassemble(&mmu, 0xC000);
+
+    assert_eq!(instruction.text, "call $1234");
+    assert_eq!(instruction.bytes, vec![0xCD, 0x34, 0x12]);
+    assert_eq!(instruction.size(), 3);
+    assert_eq!(instruction.target, Some(0x1234));
+  }
+
+  #[test]
+  fn disasm_unmapped() {
+    let mut mmu = RealMMU::new(false, vec![0; 0x8000]);
+    mmu.write8(0xDFFFu16, 0xFA);
+
+    assert_eq!(disassemble(&mmu, 0xDFFF).text, "ld a, [$FFFF]");
+    assert_eq!(mmu.take_fault(), None);
+  }
+
+  #[test]
+  fn disasm_target() {
+    let target = |bytes: &[u8]| disassemble_slice(bytes, 0x0100).target;
+
+    assert_eq!(target(&[0x18, 0xFE]), Some(0x0100));
+    assert_eq!(target(&[0xDA, 0x50, 0x01]), Some(0x0150));
+    assert_eq!(target(&[0xE9]), None);
+    assert_eq!(target(&[0xFA, 0x50, 0x01]), None);
+  }
+
+  // every opcode, the CB-prefixed ones included, disassembles to a
+  // distinct text of the right size
+  #[test]
+  fn disasm_distinct() {
+    let mut seen: HashMap<String, Vec<u8>> = HashMap::new();
+
+    for byte in 0..=255u8 {
+      if byte == 0xCB {
+        continue;
+      }
+
+      let bytes = vec![byte, 0x12, 0x34];
+
+      let instruction = disassemble_slice(&bytes, 0x0100);
+      let size = opcodes::op_size(opcodes::decode(byte));
+
+      assert_eq!(instruction.size(), size);
+      assert_eq!(seen.insert(instruction.text, bytes), None);
+    }
+
+    for byte in 0..=255u8 {
+      let bytes = vec![0xCB, byte];
+
+      let instruction = disassemble_slice(&bytes, 0x0100);
+
+      assert_eq!(instruction.size(), 2);
+      assert_eq!(seen.insert(instruction.text, bytes), None);
+    }
+
+    assert_eq!(seen.len(), 511);
+  }
+}
@@ -0,0 +1,198 @@
+// detection of idle loops: code polling memory, such as LY or a flag set by
+// an interrupt handler, in a tight loop instead of using HALT
+//
+// once a backward jump is taken, one iteration of the loop is recorded as
+// the sequence of M-cycles ticked and bytes read by each instruction. if
+// the loop writes nothing and the registers are the same when it comes
+// back around, every following iteration is bound to do exactly the same
+// for as long as the bytes read don't change, so the CPU can replay the
+// ticks alone, checking each read against the recording instead of
+// executing anything
+
+use super::registers::Registers;
+
+// loops longer than this aren't worth detecting
+const MAX_INSTRUCTIONS: usize = 16;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Access {
+  Tick,
+  Read(u16, u8),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Instruction {
+  // registers right before the instruction ran
+  pub regs: Registers,
+  pub accesses: Vec<Access>,
+}
+
+enum State {
+  // waiting for a backward jump
+  Searching,
+  // recording an iteration of the loop starting at `head`
+  Recording {
+    head: u16,
+    instructions: Vec<Instruction>,
+  },
+  // an iteration known to repeat itself
+  Idle {
+    instructions: Vec<Instruction>,
+  },
+}
+
+pub struct Detector {
+  state: State,
+}
+
+impl Detector {
+  pub fn new() -> Detector {
+    Detector {
+      state: State::Searching,
+    }
+  }
+
+  // the recorded iteration, if it is due to run again from these registers
+  pub fn idle_loop(&self, regs: &Registers) -> Option<&[Instruction]> {
+    match &self.state {
+      State::Idle { instructions } if instructions[0].regs == *regs => Some(instructions),
+      _ => None,
+    }
+  }
+
+  // called before every instruction. coming back to the head of the loop
+  // with the same registers makes it idle, otherwise recording starts over
+  pub fn begin_instruction(&mut self, regs: &Registers) {
+    let pc = regs.pc();
+
+    if let State::Recording { head, instructions } = &mut self.state {
+      if instructions.len() == MAX_INSTRUCTIONS {
+        self.state = State::Searching;
+        return;
+      }
+
+      if pc == *head && !instructions.is_empty() {
+        if instructions[0].regs == *regs {
+          let instructions = std::mem::take(instructions);
+          self.state = State::Idle { instructions };
+          return;
+        }
+
+        instructions.clear();
+      }
+
+      instructions.push(Instruction {
+        regs: regs.clone(),
+        accesses: Vec::new(),
+      });
+    }
+  }
+
+  pub fn record(&mut self, access: Access) {
+    if let State::Recording { instructions, .. } = &mut self.state {
+      if let Some(instruction) = instructions.last_mut() {
+        instruction.accesses.push(access);
+      }
+    }
+  }
+
+  // a taken jump to `target` from the instruction at `pc`
+  pub fn jumped(&mut self, target: u16, pc: u16) {
+    if target <= pc {
+      if let State::Searching = self.state {
+        self.state = State::Recording {
+          head: target,
+          instructions: Vec::new(),
+        };
+      }
+    }
+  }
+
+  // something happened that a loop can't be idle with, such as a write
+  pub fn reset(&mut self) {
+    self.state = State::Searching;
+  }
+}
+
+impl Default for Detector {
+  fn default() -> Detector {
+    Detector::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn regs(pc: u16, a: u8) -> Registers {
+    let mut regs = Registers::new();
+    regs.set_pc(pc);
+    regs.set_a(a);
+
+    regs
+  }
+
+  // ldh a, [$44] / cp $90 / jr nz, $C000
+  fn iteration(detector: &mut Detector, ly: u8) {
+    detector.begin_instruction(&regs(0xC000, 0x90));
+    detector.record(Access::Tick);
+    detector.record(Access::Read(0xFF44, ly));
+    detector.begin_instruction(&regs(0xC002, ly));
+    detector.begin_instruction(&regs(0xC004, ly));
+    detector.jumped(0xC000, 0xC004);
+  }
+
+  #[test]
+  fn detect() {
+    let mut detector = Detector::new();
+    detector.jumped(0xC000, 0xC004);
+
+    iteration(&mut detector, 0x10);
+    assert!(detector.idle_loop(&regs(0xC000, 0x90)).is_none());
+
+    detector.begin_instruction(&regs(0xC000, 0x90));
+    let instructions = detector.idle_loop(&regs(0xC000, 0x90)).unwrap();
+    assert_eq!(instructions.len(), 3);
+    assert_eq!(
+      instructions[0].accesses,
+      [Access::Tick, Access::Read(0xFF44, 0x10)]
+    );
+
+    assert!(detector.idle_loop(&regs(0xC000, 0x91)).is_none());
+  }
+
+  #[test]
+  fn registers_changing() {
+    let mut detector = Detector::new();
+    detector.jumped(0xC000, 0xC004);
+
+    // A is left different at the head each time around
+    for a in 0..4 {
+      detector.begin_instruction(&regs(0xC000, a));
+      detector.begin_instruction(&regs(0xC002, a));
+    }
+
+    assert!(detector.idle_loop(&regs(0xC000, 3)).is_none());
+  }
+
+  #[test]
+  fn reset() {
+    let mut detector = Detector::new();
+    detector.jumped(0xC000, 0xC004);
+    iteration(&mut detector, 0x10);
+    detector.reset();
+    detector.begin_instruction(&regs(0xC000, 0x90));
+
+    assert!(detector.idle_loop(&regs(0xC000, 0x90)).is_none());
+  }
+
+  #[test]
+  fn forward_jump() {
+    let mut detector = Detector::new();
+    detector.jumped(0xC010, 0xC004);
+    iteration(&mut detector, 0x10);
+    detector.begin_instruction(&regs(0xC000, 0x90));
+
+    assert!(detector.idle_loop(&regs(0xC000, 0x90)).is_none());
+  }
+}
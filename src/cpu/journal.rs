@@ -0,0 +1,134 @@
+// journal of the most recent instructions, holding what each of them
+// changed so that they can be undone one by one: the CPU state before it
+// ran, the previous value of every byte it wrote and the changes it made
+// to the call stack
+//
+// only the CPU's side of the machine is journaled. anything advanced by
+// ticking the rest of the bus, such as the GPU, isn't rewound
+
+use super::callstack::Change;
+use super::snapshot::Snapshot;
+use std::collections::VecDeque;
+
+pub struct Entry {
+  pub snapshot: Snapshot,
+  pub call_stack: Vec<Change>,
+  // address and previous value of each byte written, in order
+  pub writes: Vec<(u16, u8)>,
+}
+
+pub struct Journal {
+  entries: VecDeque<Entry>,
+  capacity: usize,
+}
+
+impl Journal {
+  // keeps up to `capacity` instructions, dropping the oldest ones
+  pub fn new(capacity: usize) -> Journal {
+    Journal {
+      entries: VecDeque::with_capacity(capacity),
+      capacity,
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.entries.clear();
+  }
+
+  // starts the entry of an instruction about to run
+  pub fn begin(&mut self, snapshot: Snapshot) {
+    if self.capacity == 0 {
+      return;
+    }
+
+    if self.entries.len() == self.capacity {
+      self.entries.pop_front();
+    }
+
+    self.entries.push_back(Entry {
+      snapshot,
+      call_stack: Vec::new(),
+      writes: Vec::new(),
+    });
+  }
+
+  pub fn record_write(&mut self, address: u16, old: u8) {
+    if let Some(entry) = self.entries.back_mut() {
+      entry.writes.push((address, old));
+    }
+  }
+
+  pub fn record_call_stack(&mut self, changes: Vec<Change>) {
+    if let Some(entry) = self.entries.back_mut() {
+      entry.call_stack = changes;
+    }
+  }
+
+  // the most recent entry, to be undone
+  pub fn pop(&mut self) -> Option<Entry> {
+    self.entries.pop_back()
+  }
+
+  // how many entries back the last write to the address is,
+  // 0 being the most recent one
+  pub fn last_write(&self, address: u16) -> Option<usize> {
+    self
+      .entries
+      .iter()
+      .rev()
+      .position(|entry| entry.writes.iter().any(|&(a, _)| a == address))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cpu::CPU;
+
+  fn begin(journal: &mut Journal, pc: u16) {
+    let mut snapshot = CPU::new().snapshot();
+    snapshot.pc = pc;
+
+    journal.begin(snapshot);
+  }
+
+  #[test]
+  fn capacity() {
+    let mut journal = Journal::new(2);
+
+    for pc in 0..3 {
+      begin(&mut journal, pc);
+    }
+
+    assert_eq!(journal.len(), 2);
+    assert_eq!(journal.pop().map(|entry| entry.snapshot.pc), Some(2));
+    assert_eq!(journal.pop().map(|entry| entry.snapshot.pc), Some(1));
+    assert!(journal.pop().is_none());
+  }
+
+  #[test]
+  fn last_write() {
+    let mut journal = Journal::new(8);
+    journal.record_write(0xC000, 1);
+    assert!(journal.is_empty());
+
+    begin(&mut journal, 0x0150);
+    journal.record_write(0xC000, 1);
+    begin(&mut journal, 0x0152);
+    journal.record_write(0xC001, 2);
+    begin(&mut journal, 0x0154);
+
+    assert_eq!(journal.last_write(0xC000), Some(2));
+    assert_eq!(journal.last_write(0xC001), Some(1));
+    assert_eq!(journal.last_write(0xC002), None);
+    assert_eq!(journal.pop().map(|entry| entry.writes.len()), Some(0));
+  }
+}
@@ -1,75 +1,745 @@
+pub mod asm;
+pub mod blocks;
+pub mod callstack;
+pub mod disasm;
+pub mod idle;
+pub mod journal;
 pub mod opcodes;
+pub mod profiler;
 pub mod registers;
+pub mod snapshot;
+pub mod trace;
 
-use super::mmu::MMU;
-use opcodes::{AluOp, ExtendedOpcode, JumpCondition, Opcode};
+#[cfg(test)]
+mod bench;
+#[cfg(test)]
+mod conformance;
+
+use super::debug::cdl;
+use super::mmu::{addrs::Addr, addrs::Interrupt, Fault, MMU};
+use blocks::BlockCache;
+use callstack::{CallStack, Frame, FrameKind};
+use idle::Access;
+use journal::Journal;
+use opcodes::{AluOp, DecodeTable, ExtendedOpcode, JumpCondition, Opcode};
+use profiler::Profiler;
 use registers::{Flag, Register16, Register8, Registers};
+use snapshot::Snapshot;
+use trace::Tracer;
 
+use std::fmt;
 use Flag::*;
 use Register16::*;
 use Register8::*;
+
+// notable conditions the CPU ran into, to be reported by the frontend
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Event {
+  IllegalOpcode { pc: u16, opcode: u8 },
+}
+
+impl fmt::Display for Event {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Event::IllegalOpcode { pc, opcode } => write!(
+        f,
+        "CPU locked up on illegal opcode {:#04x} at {:#06x}",
+        opcode, pc
+      ),
+    }
+  }
+}
+
+// errors the CPU can't carry on from, along with the instruction at fault
+#[derive(Debug, PartialEq, Clone)]
+pub enum CpuError {
+  UnsupportedRead {
+    pc: u16,
+    opcode: Vec<u8>,
+    address: u16,
+  },
+  UnsupportedWrite {
+    pc: u16,
+    opcode: Vec<u8>,
+    address: u16,
+  },
+  UnimplementedOpcode {
+    pc: u16,
+    opcode: Vec<u8>,
+  },
+}
+
+impl fmt::Display for CpuError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let bytes = |opcode: &[u8]| -> String {
+      let bytes: Vec<String> = opcode.iter().map(|byte| format!("{:02X}", byte)).collect();
+      bytes.join(" ")
+    };
+
+    match self {
+      CpuError::UnsupportedRead {
+        pc,
+        opcode,
+        address,
+      } => write!(
+        f,
+        "Unsupported read from {:#06x} by {} at {:#06x}",
+        address,
+        bytes(opcode),
+        pc
+      ),
+      CpuError::UnsupportedWrite {
+        pc,
+        opcode,
+        address,
+      } => write!(
+        f,
+        "Unsupported write to {:#06x} by {} at {:#06x}",
+        address,
+        bytes(opcode),
+        pc
+      ),
+      CpuError::UnimplementedOpcode { pc, opcode } => {
+        write!(f, "Unimplemented opcode {} at {:#06x}", bytes(opcode), pc)
+      }
+    }
+  }
+}
+
+// what a single call to `exec` did
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct StepInfo {
+  // address of the instruction executed, or PC while idle
+  pub pc: u16,
+  // CPU cycles, which pass twice as fast as clock cycles in double speed
+  pub cycles: u8,
+  // clock cycles fast-forwarded through an idle loop before the
+  // instruction
+  pub skipped: u32,
+}
+
 pub struct CPU {
   regs: Registers,
-  interrupts: u32,
+  decoder: DecodeTable,
+  ime: bool,
+  ime_scheduled: bool,
+  halted: bool,
+  halt_bug: bool,
+  stopped: bool,
+  locked: bool,
+  double_speed: bool,
+  event: Option<Event>,
+  tracer: Option<Tracer>,
+  profiler: Option<Profiler>,
+  cdl: Option<cdl::Logger>,
+  call_stack: CallStack,
+  journal: Option<Journal>,
+  idle_loops: Option<idle::Detector>,
+  blocks: Option<BlockCache>,
+  // the instruction being executed, when it came from the block cache
+  cached: Option<blocks::Instruction>,
+  // M-cycles of the next instruction already ticked while skipping
+  // an idle loop
+  skip_ticks: u8,
   cycles: u32,
+  pub cgb_mode: bool,
   pub last_instr_cycles: u8,
 }
 
 type ExecResult = (Option<u16>, u8);
 
+// interrupts in priority order, along with the address of their handler
+const INTERRUPT_VECTORS: [(Interrupt, u16); 5] = [
+  (Interrupt::VBlank, 0x40),
+  (Interrupt::LCDStat, 0x48),
+  (Interrupt::Timer, 0x50),
+  (Interrupt::Serial, 0x58),
+  (Interrupt::Joypad, 0x60),
+];
+
+const INTERRUPT_DISPATCH_CYCLES: u8 = 20;
+
+// clock cycles taken by a memory access or internal delay. in double
+// speed the CPU takes half as many, while the PPU keeps to the clock. the
+// timer and serial port, which would speed up along with the CPU, aren't
+// emulated, so they're left out of double speed for now
+const M_CYCLE: u8 = 4;
+
+// clock cycles skipped through an idle loop at most before handing control
+// back, a frame's worth at either speed
+const MAX_SKIPPED_CYCLES: u32 = 70224;
+
 impl CPU {
   #[allow(dead_code)]
   pub fn new() -> CPU {
     CPU {
       regs: Registers::new(),
-      interrupts: 0,
+      decoder: DecodeTable::new(),
+      ime: false,
+      ime_scheduled: false,
+      halted: false,
+      halt_bug: false,
+      stopped: false,
+      locked: false,
+      double_speed: false,
+      event: None,
+      tracer: None,
+      profiler: None,
+      cdl: None,
+      call_stack: CallStack::new(),
+      journal: None,
+      idle_loops: None,
+      blocks: None,
+      cached: None,
+      skip_ticks: 0,
       cycles: 0,
+      cgb_mode: false,
       last_instr_cycles: 0,
     }
   }
 
-  // executes the next instruction referenced by PC
+  // executes the next instruction referenced by PC,
+  // or dispatches a pending interrupt instead
   #[allow(dead_code)]
-  pub fn exec<M: MMU>(&mut self, mmu: &mut M) {
-    let current_pc = self.regs.read16(PC);
+  pub fn exec<M: MMU>(&mut self, mmu: &mut M) -> Result<StepInfo, CpuError> {
+    let skipped = self.skip_idle_loop(mmu);
+    // in double speed, every clock cycle skipped is two CPU cycles
+    let skipped_cycles = skipped / self.m_cycle() as u32 * M_CYCLE as u32;
+    let pc = self.regs.pc();
+
+    if let Some(mut journal) = self.journal.take() {
+      journal.begin(self.snapshot());
+      self.journal = Some(journal);
+    }
+
+    let result = self.step(mmu);
+
+    if let Some(journal) = self.journal.as_mut() {
+      journal.record_call_stack(self.call_stack.take_changes());
+    }
+
+    let cycles = result?;
+
+    if let Some(fault) = mmu.take_fault() {
+      let opcode = self.instruction_bytes(mmu, pc);
+
+      return Err(match fault {
+        Fault::Read(address) => CpuError::UnsupportedRead {
+          pc,
+          opcode,
+          address,
+        },
+        Fault::Write(address) => CpuError::UnsupportedWrite {
+          pc,
+          opcode,
+          address,
+        },
+      });
+    }
+
+    self.last_instr_cycles = cycles;
+    // the counter wraps around after about 17 minutes of emulated time
+    self.cycles = self.cycles.wrapping_add(skipped_cycles + cycles as u32);
+
+    Ok(StepInfo {
+      pc,
+      cycles,
+      skipped,
+    })
+  }
+
+  // fast-forwards through the idle loop the CPU is at the head of, if any,
+  // returning the cycles skipped. nothing is skipped while anything is
+  // following every instruction executed
+  fn skip_idle_loop<M: MMU>(&mut self, mmu: &mut M) -> u32 {
+    if self.idle()
+      || self.ime_scheduled
+      || self.tracer.is_some()
+      || self.profiler.is_some()
+      || self.cdl.is_some()
+      || self.journal.is_some()
+    {
+      return 0;
+    }
+
+    let mut detector = match self.idle_loops.take() {
+      Some(detector) => detector,
+      None => return 0,
+    };
+
+    let skipped = match detector.idle_loop(&self.regs) {
+      Some(instructions) => {
+        let (skipped, left) = self.replay(instructions, mmu);
+
+        if left {
+          detector.reset();
+        }
+
+        skipped
+      }
+      None => 0,
+    };
+
+    self.idle_loops = Some(detector);
+
+    skipped
+  }
+
+  // replays iterations of an idle loop, ticking the M-cycles it would have,
+  // until one of the bytes it reads changes, an interrupt is due or enough
+  // cycles have been skipped. returns the cycles skipped and whether the
+  // loop is about to be left
+  fn replay<M: MMU>(&mut self, instructions: &[idle::Instruction], mmu: &mut M) -> (u32, bool) {
+    let mut skipped = 0;
+
+    loop {
+      for (index, instruction) in instructions.iter().enumerate() {
+        if index == 0 && skipped >= MAX_SKIPPED_CYCLES {
+          return (skipped, false);
+        }
+
+        if self.ime && self.pending_interrupts(mmu) != 0 {
+          self.regs = instruction.regs.clone();
+          return (skipped, true);
+        }
+
+        let mut ticks = 0;
+
+        for access in &instruction.accesses {
+          match *access {
+            Access::Tick => {
+              mmu.tick(self.m_cycle());
+              ticks += 1;
+            }
+            // the instruction is executed from the start, with the
+            // M-cycles up to this read already ticked
+            Access::Read(address, value) => {
+              if mmu.peek8(address) != Some(value) {
+                self.regs = instruction.regs.clone();
+                self.skip_ticks = ticks;
+                return (skipped, true);
+              }
+            }
+          }
+        }
+
+        skipped += ticks as u32 * self.m_cycle() as u32;
+      }
+    }
+  }
+
+  fn step<M: MMU>(&mut self, mmu: &mut M) -> Result<u8, CpuError> {
+    // after an illegal opcode the CPU hangs for good, not even interrupts
+    // can wake it up
+    if self.locked {
+      mmu.tick(self.m_cycle());
+      return Ok(4);
+    }
+
+    // in STOP mode, nothing runs until a selected joypad line goes low,
+    // so the rest of the system isn't ticked either
+    if self.stopped {
+      if mmu.peek8(Addr::Joypad as u16).unwrap_or(0xFF) & 0x0F == 0x0F {
+        return Ok(4);
+      }
+
+      self.stopped = false;
+    }
+
+    // while halted, time keeps passing until an interrupt is pending,
+    // regardless of IME
+    if self.halted {
+      if self.pending_interrupts(mmu) == 0 {
+        mmu.tick(self.m_cycle());
+        return Ok(4);
+      }
 
-    let byte = mmu.read8(current_pc as usize);
-    let opcode = opcodes::decode(byte);
+      self.halted = false;
+    }
+
+    // an instruction partly ticked through while skipping an idle loop has
+    // already started, so interrupts wait until it's done
+    if self.skip_ticks == 0 && self.dispatch_interrupt(mmu) {
+      return Ok(INTERRUPT_DISPATCH_CYCLES);
+    }
+
+    // EI only takes effect after the instruction following it
+    let enable_ime = self.ime_scheduled;
+
+    if let Some(tracer) = self.tracer.as_mut() {
+      if let Err(err) = tracer.trace(&self.regs, mmu) {
+        eprintln!("Disabling trace: {}", err);
+        self.tracer = None;
+      }
+    }
+
+    if let Some(detector) = self.idle_loops.as_mut() {
+      detector.begin_instruction(&self.regs);
+    }
+
+    let mut current_pc = self.regs.read16(PC);
+
+    self.cached = match self.blocks.as_mut() {
+      Some(blocks) => blocks.instruction(mmu, current_pc),
+      None => None,
+    };
+
+    let byte = self.fetch_code8(mmu, current_pc, 0, cdl::EXEC_FIRST);
+    let (opcode, size) = match self.cached {
+      Some(instruction) => (instruction.opcode, instruction.size),
+      None => self.decoder.decode(byte),
+    };
+
+    // loops changing IME or waiting on their own are never idle
+    if let Some(detector) = self.idle_loops.as_mut() {
+      match opcode {
+        Opcode::EI | Opcode::DI | Opcode::RETI | Opcode::HALT | Opcode::STOP => detector.reset(),
+        _ => (),
+      }
+    }
+
+    // HALT bug: PC fails to increment after fetching this opcode,
+    // so its first byte is read again as the next one
+    if self.halt_bug {
+      self.halt_bug = false;
+      current_pc = current_pc.wrapping_sub(1);
+      self.regs.set_pc(current_pc);
+      self.cached = None;
+    }
+
+    let (jump_to, cycles) = self.exec_opcode(opcode, current_pc, mmu)?;
+
+    if let (Some(detector), Opcode::JUMP(..), Some(target)) =
+      (self.idle_loops.as_mut(), opcode, jump_to)
+    {
+      detector.jumped(target, current_pc);
+    }
+
+    match (opcode, jump_to) {
+      (Opcode::CALL(..), Some(target)) => self.enter(FrameKind::Call, current_pc, target, mmu),
+      (Opcode::RST(_), Some(target)) => self.enter(FrameKind::Rst, current_pc, target, mmu),
+      // the return address was popped from just below SP
+      (Opcode::RET(_), Some(_)) | (Opcode::RETI, Some(_)) => {
+        self.call_stack.ret(self.regs.sp().wrapping_sub(2))
+      }
+      _ => (),
+    }
+
+    if let Some(profiler) = self.profiler.as_mut() {
+      let bank = mmu.rom_bank(current_pc);
+      profiler.record(bank, current_pc, cycles);
 
-    let (jump_to, cycles) = self.exec_opcode(opcode, current_pc, mmu);
+      match (opcode, jump_to) {
+        (Opcode::CALL(..), Some(target)) | (Opcode::RST(_), Some(target)) => {
+          profiler.record_call(mmu.rom_bank(target), target)
+        }
+        (Opcode::JUMP(..), Some(target)) if target <= current_pc => {
+          profiler.record_loop(bank, target, current_pc)
+        }
+        _ => (),
+      }
+    }
 
     let new_pc = match jump_to {
       Some(new_pc) => new_pc,
-      None => current_pc + opcodes::op_size(opcode),
+      None => current_pc.wrapping_add(size),
     };
 
+    // nothing else will be traced after a lock up
+    if self.locked {
+      self.flush_tracer();
+    }
+
+    if enable_ime && self.ime_scheduled {
+      self.ime_scheduled = false;
+      self.ime = true;
+    }
+
     self.regs.set_pc(new_pc);
-    self.last_instr_cycles = cycles;
-    self.cycles += cycles as u32;
+
+    Ok(cycles)
+  }
+
+  pub fn regs(&self) -> &Registers {
+    &self.regs
+  }
+
+  pub fn snapshot(&self) -> Snapshot {
+    Snapshot {
+      af: self.regs.af(),
+      bc: self.regs.bc(),
+      de: self.regs.de(),
+      hl: self.regs.hl(),
+      sp: self.regs.sp(),
+      pc: self.regs.pc(),
+      ime: self.ime,
+      ime_scheduled: self.ime_scheduled,
+      halted: self.halted,
+      halt_bug: self.halt_bug,
+      stopped: self.stopped,
+      locked: self.locked,
+      double_speed: self.double_speed,
+      cgb_mode: self.cgb_mode,
+      cycles: self.cycles,
+      last_instr_cycles: self.last_instr_cycles,
+    }
+  }
+
+  // pending events are dropped, as they belong to the state being replaced
+  pub fn restore(&mut self, snapshot: &Snapshot) {
+    self.restore_registers(snapshot);
+    self.call_stack.clear();
+  }
+
+  fn restore_registers(&mut self, snapshot: &Snapshot) {
+    self.regs.set_af(snapshot.af);
+    self.regs.set_bc(snapshot.bc);
+    self.regs.set_de(snapshot.de);
+    self.regs.set_hl(snapshot.hl);
+    self.regs.set_sp(snapshot.sp);
+    self.regs.set_pc(snapshot.pc);
+    self.ime = snapshot.ime;
+    self.ime_scheduled = snapshot.ime_scheduled;
+    self.halted = snapshot.halted;
+    self.halt_bug = snapshot.halt_bug;
+    self.stopped = snapshot.stopped;
+    self.locked = snapshot.locked;
+    self.double_speed = snapshot.double_speed;
+    self.cgb_mode = snapshot.cgb_mode;
+    self.cycles = snapshot.cycles;
+    self.last_instr_cycles = snapshot.last_instr_cycles;
+    self.event = None;
+  }
+
+  // whether the CPU is in STOP mode, in which the LCD is off as well
+  pub fn stopped(&self) -> bool {
+    self.stopped
+  }
+
+  // whether the next call to `exec` won't execute the instruction at PC,
+  // because the CPU is waiting for something to happen
+  pub fn idle(&self) -> bool {
+    self.halted || self.stopped || self.locked
+  }
+
+  // whether a CGB is running at 8 MHz, switched to with KEY1 and STOP
+  pub fn double_speed(&self) -> bool {
+    self.double_speed
+  }
+
+  // traces every instruction executed from now on
+  pub fn set_tracer(&mut self, tracer: Tracer) {
+    self.tracer = Some(tracer);
+  }
+
+  // writes out the trace lines buffered so far
+  pub fn flush_tracer(&mut self) {
+    if let Some(tracer) = self.tracer.as_mut() {
+      let _ = tracer.flush();
+    }
+  }
+
+  // profiles every instruction executed from now on
+  pub fn set_profiler(&mut self, profiler: Profiler) {
+    self.profiler = Some(profiler);
+  }
+
+  pub fn take_profiler(&mut self) -> Option<Profiler> {
+    self.profiler.take()
+  }
+
+  // logs every ROM byte executed or read from now on
+  pub fn set_code_data_logger(&mut self, logger: cdl::Logger) {
+    self.cdl = Some(logger);
+  }
+
+  pub fn take_code_data_logger(&mut self) -> Option<cdl::Logger> {
+    self.cdl.take()
+  }
+
+  // journals every instruction executed from now on, to step back with
+  #[allow(dead_code)]
+  pub fn set_journal(&mut self, journal: Journal) {
+    self.journal = Some(journal);
+    self.call_stack.record_changes(true);
+  }
+
+  #[allow(dead_code)]
+  pub fn take_journal(&mut self) -> Option<Journal> {
+    self.call_stack.record_changes(false);
+    self.journal.take()
+  }
+
+  // fast-forwards through loops found to be polling memory from now on,
+  // with the same results as executing them
+  pub fn set_idle_loop_detection(&mut self, enabled: bool) {
+    self.idle_loops = if enabled {
+      Some(idle::Detector::new())
+    } else {
+      None
+    };
+  }
+
+  // runs straight-line code from cached basic blocks instead of decoding
+  // every instruction, with the same results
+  pub fn set_block_cache(&mut self, enabled: bool) {
+    if !enabled {
+      self.blocks = None;
+      self.cached = None;
+    } else if self.blocks.is_none() {
+      self.blocks = Some(BlockCache::new());
+    }
+  }
+
+  // undoes the last journaled instruction, returning whether there was one
+  #[allow(dead_code)]
+  pub fn step_back<M: MMU>(&mut self, mmu: &mut M) -> bool {
+    let entry = match self.journal.as_mut().and_then(|journal| journal.pop()) {
+      Some(entry) => entry,
+      None => return false,
+    };
+
+    for &(address, old) in entry.writes.iter().rev() {
+      if let Some(blocks) = self.blocks.as_mut() {
+        blocks.will_write(address);
+      }
+
+      mmu.poke8(address, old);
+    }
+
+    self.restore_registers(&entry.snapshot);
+    self.call_stack.undo(&entry.call_stack);
+
+    true
+  }
+
+  // undoes instructions up to and including the last journaled write to
+  // the address, leaving PC on the instruction that wrote it. nothing is
+  // undone when the journal doesn't go back that far
+  #[allow(dead_code)]
+  pub fn step_back_to_write<M: MMU>(&mut self, mmu: &mut M, address: u16) -> bool {
+    let steps = match self.journal.as_ref().and_then(|j| j.last_write(address)) {
+      Some(back) => back + 1,
+      None => return false,
+    };
+
+    for _ in 0..steps {
+      self.step_back(mmu);
+    }
+
+    true
+  }
+
+  // routines and interrupt handlers entered and not yet returned from
+  pub fn call_stack(&self) -> &CallStack {
+    &self.call_stack
+  }
+
+  // takes the last event raised during execution, if any
+  pub fn take_event(&mut self) -> Option<Event> {
+    self.event.take()
+  }
+
+  // bytes of the instruction at the given address, for error reports.
+  // the instruction itself may lie in unmapped memory, which reads high
+  fn instruction_bytes<M: MMU>(&self, mmu: &M, pc: u16) -> Vec<u8> {
+    let byte = |address: u16| mmu.peek8(address).unwrap_or(0xFF);
+    let (_, size) = self.decoder.decode(byte(pc));
+
+    (0..size).map(|i| byte(pc.wrapping_add(i))).collect()
+  }
+
+  // records a frame for a call or interrupt that just pushed its
+  // return address
+  fn enter<M: MMU>(&mut self, kind: FrameKind, caller: u16, target: u16, mmu: &M) {
+    self.call_stack.push(Frame {
+      kind,
+      caller,
+      caller_bank: mmu.rom_bank(caller),
+      target,
+      sp: self.regs.sp(),
+    });
+  }
+
+  // interrupts requested in IF and enabled in IE
+  fn pending_interrupts<M: MMU>(&self, mmu: &M) -> u8 {
+    let read = |address: Addr| mmu.peek8(address as u16).unwrap_or(0);
+
+    read(Addr::InterruptFlag) & read(Addr::InterruptEnable) & 0x1F
+  }
+
+  // if IME is set and an interrupt is pending, acknowledges the one with the
+  // highest priority, pushes PC and jumps to its vector
+  fn dispatch_interrupt<M: MMU>(&mut self, mmu: &mut M) -> bool {
+    if !self.ime {
+      return false;
+    }
+
+    let pending = self.pending_interrupts(mmu);
+
+    match INTERRUPT_VECTORS
+      .iter()
+      .find(|(interrupt, _)| pending & u8::from(*interrupt) > 0)
+    {
+      Some(&(interrupt, vector)) => {
+        self.will_write(mmu, Addr::InterruptFlag as u16);
+        mmu.unset_flag(Addr::InterruptFlag, interrupt);
+        self.ime = false;
+        self.ime_scheduled = false;
+        self.tick(mmu);
+        self.tick(mmu);
+
+        // after EI / HALT, the HALT bug leaves PC on HALT, which is where
+        // the interrupt returns to
+        let pc = if self.halt_bug {
+          self.halt_bug = false;
+          self.regs.pc().wrapping_sub(1)
+        } else {
+          self.regs.pc()
+        };
+
+        self.push(pc, mmu);
+        self.enter(FrameKind::Interrupt, pc, vector, mmu);
+        self.regs.set_pc(vector);
+
+        true
+      }
+      None => false,
+    }
   }
 
-  // executes the given opcode
+  // executes the given opcode, ticking the MMU on every memory access and
+  // internal delay. the returned cycles include the opcode fetch
   #[allow(unused_macros)]
-  fn exec_opcode<M: MMU>(&mut self, opcode: Opcode, pc: u16, mmu: &mut M) -> ExecResult {
+  fn exec_opcode<M: MMU>(
+    &mut self,
+    opcode: Opcode,
+    pc: u16,
+    mmu: &mut M,
+  ) -> Result<ExecResult, CpuError> {
     use opcodes::{Arg::*, JumpCondition::*, Opcode::*};
 
-    match opcode {
+    Ok(match opcode {
       NOP => (None, 4),
 
       LD(Addr16, Reg16(reg16)) => {
-        mmu.write16(self.read_arg16(mmu) as usize, self.regs.read16(reg16));
+        let ptr = self.read_arg16(mmu);
+        let v = self.regs.read16(reg16);
+        self.write16(mmu, ptr, v);
 
-        (None, 16)
+        (None, 20)
       }
 
       LD(Reg16(reg16), Imm16) => {
-        self.regs.write16(reg16, self.read_arg16(mmu));
+        let v = self.read_arg16(mmu);
+        self.regs.write16(reg16, v);
 
         (None, 12)
       }
 
       ADD(Reg16(HL), Reg16(reg16)) => {
         self.alu_add_hl(self.regs.read16(reg16));
+        self.tick(mmu);
 
         (None, 8)
       }
@@ -78,6 +748,7 @@ impl CPU {
         self
           .regs
           .write16(reg16, self.regs.read16(reg16).wrapping_add(1));
+        self.tick(mmu);
 
         (None, 8)
       }
@@ -86,6 +757,7 @@ impl CPU {
         self
           .regs
           .write16(reg16, self.regs.read16(reg16).wrapping_sub(1));
+        self.tick(mmu);
 
         (None, 8)
       }
@@ -98,9 +770,10 @@ impl CPU {
       }
 
       INC(PtrReg16(reg16)) => {
-        let ptr: usize = self.regs.read16(reg16) as usize;
-        let v = self.alu_inc(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_inc(d);
+        self.write8(mmu, ptr, v);
 
         (None, 12)
       }
@@ -109,26 +782,28 @@ impl CPU {
         let v = self.alu_dec(self.regs.read8(reg8));
         self.regs.write8(reg8, v);
 
-        (None, 8)
+        (None, 4)
       }
 
       DEC(PtrReg16(reg16)) => {
-        let ptr: usize = self.regs.read16(reg16) as usize;
-        let v = self.alu_dec(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_dec(d);
+        self.write8(mmu, ptr, v);
 
         (None, 12)
       }
 
       LD(Reg8(reg8), Imm8) => {
-        self.regs.write8(reg8, self.read_arg8(mmu));
+        let v = self.read_arg8(mmu);
+        self.regs.write8(reg8, v);
 
         (None, 8)
       }
 
       LD(PtrReg16(reg16), Imm8) => {
-        let ptr: usize = self.regs.read16(reg16) as usize;
-        mmu.write8(ptr, self.read_arg8(mmu));
+        let v = self.read_arg8(mmu);
+        self.write8(mmu, self.regs.read16(reg16), v);
 
         (None, 12)
       }
@@ -163,54 +838,63 @@ impl CPU {
       }
 
       STOP => {
-        panic!("not done yet");
+        self.will_write(mmu, Addr::Divider as u16);
+        mmu.write8(Addr::Divider, 0);
+
+        // on CGB, STOP with KEY1 armed switches speed instead of stopping
+        let key1 = mmu.peek8(Addr::SpeedSwitch as u16).unwrap_or(0);
+
+        if self.cgb_mode && key1 & 0b0000_0001 > 0 {
+          self.double_speed = !self.double_speed;
+          let speed = if self.double_speed { 0b1000_0000 } else { 0 };
+          self.will_write(mmu, Addr::SpeedSwitch as u16);
+          mmu.write8(Addr::SpeedSwitch, speed);
+        } else {
+          self.stopped = true;
+        }
 
-        // (None, 4)
+        (None, 4)
       }
 
       JUMP(condition, Imm8) => {
+        let arg = self.read_arg8(mmu);
+
         if self.check_jump_condition(condition) {
-          let arg = self.read_arg8(mmu);
-          let displacement: i8 = unsafe { std::mem::transmute::<u8, i8>(arg) };
-          let abs_displacement: u16 = i8::abs(displacement) as u16;
-
-          if displacement < 0 {
-            (Some(pc - abs_displacement + 2), 12)
-          } else {
-            (Some(pc + abs_displacement + 2), 12)
-          }
+          // relative to the next instruction, wrapping around the address space
+          let displacement = arg as i8 as u16;
+          self.tick(mmu);
+
+          (Some(pc.wrapping_add(2).wrapping_add(displacement)), 12)
         } else {
           (None, 8)
         }
       }
 
       LDI(PtrReg16(reg16), Reg8(reg8)) => {
-        mmu.write8(self.regs.read16(reg16) as usize, self.regs.read8(reg8));
+        self.write8(mmu, self.regs.read16(reg16), self.regs.read8(reg8));
         self.regs.set_hl(self.regs.read16(reg16).wrapping_add(1));
 
         (None, 8)
       }
 
       LDI(Reg8(reg8), PtrReg16(reg16)) => {
-        self
-          .regs
-          .write8(reg8, mmu.read8(self.regs.read16(reg16) as usize));
+        let v = self.read8(mmu, self.regs.read16(reg16));
+        self.regs.write8(reg8, v);
         self.regs.set_hl(self.regs.read16(reg16).wrapping_add(1));
 
         (None, 8)
       }
 
       LDD(PtrReg16(reg16), Reg8(reg8)) => {
-        mmu.write8(self.regs.read16(reg16) as usize, self.regs.read8(reg8));
+        self.write8(mmu, self.regs.read16(reg16), self.regs.read8(reg8));
         self.regs.set_hl(self.regs.read16(reg16).wrapping_sub(1));
 
         (None, 8)
       }
 
       LDD(Reg8(reg8), PtrReg16(reg16)) => {
-        self
-          .regs
-          .write8(reg8, mmu.read8(self.regs.read16(reg16) as usize));
+        let v = self.read8(mmu, self.regs.read16(reg16));
+        self.regs.write8(reg8, v);
         self.regs.set_hl(self.regs.read16(reg16).wrapping_sub(1));
 
         (None, 8)
@@ -253,29 +937,32 @@ impl CPU {
       }
 
       LD(Reg8(reg8_dest), PtrReg16(reg16)) => {
-        self
-          .regs
-          .write8(reg8_dest, mmu.read8(self.regs.read16(reg16) as usize));
+        let v = self.read8(mmu, self.regs.read16(reg16));
+        self.regs.write8(reg8_dest, v);
 
         (None, 8)
       }
 
       LD(PtrReg16(reg16), Reg8(reg8_orig)) => {
-        mmu.write8(self.regs.read16(reg16) as usize, self.regs.read8(reg8_orig));
+        self.write8(mmu, self.regs.read16(reg16), self.regs.read8(reg8_orig));
 
         (None, 8)
       }
 
       HALT => {
-        panic!("not done yet");
+        if !self.ime && self.pending_interrupts(mmu) != 0 {
+          self.halt_bug = true;
+        } else {
+          self.halted = true;
+        }
 
-        // (None, 4)
+        (None, 4)
       }
 
       ALU(op, Reg8(A), from) => {
         let (d, cycles) = match from {
           Reg8(r) => (self.regs.read8(r), 4),
-          PtrReg16(r) => (mmu.read8(self.regs.read16(r) as usize), 8),
+          PtrReg16(r) => (self.read8(mmu, self.regs.read16(r)), 8),
           Imm8 => (self.read_arg8(mmu), 8),
           _ => unreachable!(),
         };
@@ -304,99 +991,63 @@ impl CPU {
         (Some((n as u16) << 3), 16)
       }
 
-      RET(NotZero) => {
-        if !self.regs.get_flag(ZF) {
-          (Some(self.pop(mmu)), 20)
-        } else {
-          (None, 8)
-        }
-      }
+      RET(Always) => {
+        let v = self.pop(mmu);
+        self.tick(mmu);
 
-      RET(Zero) => {
-        if self.regs.get_flag(ZF) {
-          (Some(self.pop(mmu)), 20)
-        } else {
-          (None, 8)
-        }
+        (Some(v), 16)
       }
 
-      RET(NotCarry) => {
-        if !self.regs.get_flag(CF) {
-          (Some(self.pop(mmu)), 20)
-        } else {
-          (None, 8)
-        }
-      }
+      RET(condition) => {
+        self.tick(mmu);
 
-      RET(Carry) => {
-        if self.regs.get_flag(CF) {
-          (Some(self.pop(mmu)), 20)
+        if self.check_jump_condition(condition) {
+          let v = self.pop(mmu);
+          self.tick(mmu);
+
+          (Some(v), 20)
         } else {
           (None, 8)
         }
       }
 
-      RET(Always) => (Some(self.pop(mmu)), 16),
-
       RETI => {
-        println!("RETI");
-        self.interrupts = 1;
+        self.ime = true;
+        let v = self.pop(mmu);
+        self.tick(mmu);
 
-        (Some(self.pop(mmu)), 16)
+        (Some(v), 16)
       }
 
       JUMP(condition, Addr16) => {
+        let v = self.read_arg16(mmu);
+
         if self.check_jump_condition(condition) {
-          (Some(self.read_arg16(mmu)), 12)
-        } else {
-          (None, 8)
-        }
-      }
+          self.tick(mmu);
 
-      CALL(NotZero, Addr16) => {
-        if !self.regs.get_flag(ZF) {
-          self.push(pc + 3, mmu);
-          (Some(self.read_arg16(mmu)), 24)
+          (Some(v), 16)
         } else {
           (None, 12)
         }
       }
 
-      CALL(Zero, Addr16) => {
-        if self.regs.get_flag(ZF) {
-          self.push(pc + 3, mmu);
-          (Some(self.read_arg16(mmu)), 24)
-        } else {
-          (None, 12)
-        }
-      }
+      CALL(condition, Addr16) => {
+        let v = self.read_arg16(mmu);
 
-      CALL(NotCarry, Addr16) => {
-        if !self.regs.get_flag(CF) {
-          self.push(pc + 3, mmu);
-          (Some(self.read_arg16(mmu)), 24)
-        } else {
-          (None, 12)
-        }
-      }
+        if self.check_jump_condition(condition) {
+          self.push(pc.wrapping_add(3), mmu);
 
-      CALL(Carry, Addr16) => {
-        if self.regs.get_flag(CF) {
-          self.push(pc + 3, mmu);
-          (Some(self.read_arg16(mmu)), 24)
+          (Some(v), 24)
         } else {
           (None, 12)
         }
       }
 
-      CALL(Always, Addr16) => {
-        self.push(pc + 3, mmu);
-        (Some(self.read_arg16(mmu)), 24)
-      }
-
       ADD(Reg16(reg16), Imm8) => {
         let v = self.alu_add16imm(self.regs.read16(reg16), mmu);
         self.regs.write16(reg16, v);
+        self.tick(mmu);
+        self.tick(mmu);
 
         (None, 16)
       }
@@ -404,48 +1055,52 @@ impl CPU {
       LD(Reg16(reg16), SPPlusImm8) => {
         let v = self.alu_add16imm(self.regs.sp(), mmu);
         self.regs.write16(reg16, v);
+        self.tick(mmu);
 
         (None, 12)
       }
 
       LD(HighMemImm8, Reg8(reg8)) => {
-        let ptr = (0xFF00 | self.read_arg8(mmu) as u16) as usize;
-        mmu.write8(ptr, self.regs.read8(reg8));
+        let ptr = 0xFF00 | self.read_arg8(mmu) as u16;
+        self.write8(mmu, ptr, self.regs.read8(reg8));
 
         (None, 12)
       }
 
       LD(Reg8(reg8), HighMemImm8) => {
-        let ptr = (0xFF00 | self.read_arg8(mmu) as u16) as usize;
-        self.regs.write8(reg8, mmu.read8(ptr));
+        let ptr = 0xFF00 | self.read_arg8(mmu) as u16;
+        let v = self.read8(mmu, ptr);
+        self.regs.write8(reg8, v);
 
         (None, 12)
       }
 
       LD(HighMemReg8(reg8_dest), Reg8(reg8_orig)) => {
-        let ptr = (0xFF00 | self.regs.read8(reg8_dest) as u16) as usize;
-        mmu.write8(ptr, self.regs.read8(reg8_orig));
+        let ptr = 0xFF00 | self.regs.read8(reg8_dest) as u16;
+        self.write8(mmu, ptr, self.regs.read8(reg8_orig));
 
         (None, 8)
       }
 
       LD(Reg8(reg8_dest), HighMemReg8(reg8_orig)) => {
-        let ptr = (0xFF00 | self.regs.read8(reg8_orig) as u16) as usize;
-        self.regs.write8(reg8_dest, mmu.read8(ptr));
+        let ptr = 0xFF00 | self.regs.read8(reg8_orig) as u16;
+        let v = self.read8(mmu, ptr);
+        self.regs.write8(reg8_dest, v);
 
         (None, 8)
       }
 
       LD(Addr16, Reg8(reg8)) => {
-        let ptr = self.read_arg16(mmu) as usize;
-        mmu.write8(ptr, self.regs.read8(reg8));
+        let ptr = self.read_arg16(mmu);
+        self.write8(mmu, ptr, self.regs.read8(reg8));
 
         (None, 16)
       }
 
       LD(Reg8(reg8), Addr16) => {
-        let ptr = self.read_arg16(mmu) as usize;
-        self.regs.write8(reg8, mmu.read8(ptr));
+        let ptr = self.read_arg16(mmu);
+        let v = self.read8(mmu, ptr);
+        self.regs.write8(reg8, v);
 
         (None, 16)
       }
@@ -454,39 +1109,51 @@ impl CPU {
 
       LD(Reg16(reg16_dest), Reg16(reg16_orig)) => {
         self.regs.write16(reg16_dest, self.regs.read16(reg16_orig));
+        self.tick(mmu);
 
         (None, 8)
       }
 
       DI => {
-        println!("DI");
-        self.interrupts = 0;
+        self.ime = false;
+        self.ime_scheduled = false;
 
         (None, 4)
       }
 
       EI => {
-        println!("EI");
-        self.interrupts = 1;
+        self.ime_scheduled = true;
 
         (None, 4)
       }
 
       CALLBACK => {
-        let extended_opcode = opcodes::decode_extended(self.read_arg8(mmu));
-        (None, self.exec_cb(extended_opcode, mmu))
+        let byte = self.read_arg8(mmu);
+        let extended_opcode = self.decoder.decode_extended(byte);
+        (None, self.exec_cb(extended_opcode, mmu)?)
       }
 
-      _ => unreachable!("Unexpected opcode: {:?}", opcode),
-    }
+      ILLEGAL(opcode) => {
+        self.locked = true;
+        self.event = Some(Event::IllegalOpcode { pc, opcode });
+
+        (Some(pc), 4)
+      }
+
+      _ => return Err(self.unimplemented(mmu)),
+    })
   }
 
-  fn exec_cb<M: MMU>(&mut self, decoded_opcode: ExtendedOpcode, mmu: &mut M) -> u8 {
+  fn exec_cb<M: MMU>(
+    &mut self,
+    decoded_opcode: ExtendedOpcode,
+    mmu: &mut M,
+  ) -> Result<u8, CpuError> {
     use opcodes::{Arg::*, ExtendedOpcode::*};
 
     // println!("   {:?}", decoded_opcode);
 
-    match decoded_opcode {
+    Ok(match decoded_opcode {
       RLC(Reg8(reg8)) => {
         let v = self.alu_rlc(self.regs.read8(reg8));
         self.regs.write8(reg8, v);
@@ -495,9 +1162,10 @@ impl CPU {
       }
 
       RLC(PtrReg16(reg16)) => {
-        let ptr = self.regs.read16(reg16) as usize;
-        let v = self.alu_rlc(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_rlc(d);
+        self.write8(mmu, ptr, v);
 
         16
       }
@@ -510,9 +1178,10 @@ impl CPU {
       }
 
       RRC(PtrReg16(reg16)) => {
-        let ptr = self.regs.read16(reg16) as usize;
-        let v = self.alu_rrc(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_rrc(d);
+        self.write8(mmu, ptr, v);
 
         16
       }
@@ -525,9 +1194,10 @@ impl CPU {
       }
 
       RL(PtrReg16(reg16)) => {
-        let ptr = self.regs.read16(reg16) as usize;
-        let v = self.alu_rl(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_rl(d);
+        self.write8(mmu, ptr, v);
 
         16
       }
@@ -540,9 +1210,10 @@ impl CPU {
       }
 
       RR(PtrReg16(reg16)) => {
-        let ptr = self.regs.read16(reg16) as usize;
-        let v = self.alu_rr(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_rr(d);
+        self.write8(mmu, ptr, v);
 
         16
       }
@@ -555,9 +1226,10 @@ impl CPU {
       }
 
       SLA(PtrReg16(reg16)) => {
-        let ptr = self.regs.read16(reg16) as usize;
-        let v = self.alu_sla(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_sla(d);
+        self.write8(mmu, ptr, v);
 
         16
       }
@@ -570,9 +1242,10 @@ impl CPU {
       }
 
       SRA(PtrReg16(reg16)) => {
-        let ptr = self.regs.read16(reg16) as usize;
-        let v = self.alu_sra(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_sra(d);
+        self.write8(mmu, ptr, v);
 
         16
       }
@@ -585,9 +1258,10 @@ impl CPU {
       }
 
       SWAP(PtrReg16(reg16)) => {
-        let ptr = self.regs.read16(reg16) as usize;
-        let v = self.alu_swap(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_swap(d);
+        self.write8(mmu, ptr, v);
 
         16
       }
@@ -600,9 +1274,10 @@ impl CPU {
       }
 
       SRL(PtrReg16(reg16)) => {
-        let ptr = self.regs.read16(reg16) as usize;
-        let v = self.alu_srl(mmu.read8(ptr));
-        mmu.write8(ptr, v);
+        let ptr = self.regs.read16(reg16);
+        let d = self.read8(mmu, ptr);
+        let v = self.alu_srl(d);
+        self.write8(mmu, ptr, v);
 
         16
       }
@@ -616,7 +1291,7 @@ impl CPU {
       }
 
       BIT(n, PtrReg16(reg16)) => {
-        let v = mmu.read8(self.regs.read16(reg16) as usize);
+        let v = self.read8(mmu, self.regs.read16(reg16));
 
         self.alu_bit(n, v);
 
@@ -632,9 +1307,10 @@ impl CPU {
       }
 
       RES(n, PtrReg16(reg16)) => {
-        let v = mmu.read8(self.regs.read16(reg16) as usize);
+        let ptr = self.regs.read16(reg16);
+        let v = self.read8(mmu, ptr);
 
-        mmu.write8(self.regs.read16(reg16) as usize, v & !(1 << n));
+        self.write8(mmu, ptr, v & !(1 << n));
 
         16
       }
@@ -648,14 +1324,25 @@ impl CPU {
       }
 
       SET(n, PtrReg16(reg16)) => {
-        let v = mmu.read8(self.regs.read16(reg16) as usize);
+        let ptr = self.regs.read16(reg16);
+        let v = self.read8(mmu, ptr);
 
-        mmu.write8(self.regs.read16(reg16) as usize, v | (1 << n));
+        self.write8(mmu, ptr, v | (1 << n));
 
         16
       }
 
-      opcode => unreachable!("Unknown extended opcode #{:?}", opcode),
+      _ => return Err(self.unimplemented(mmu)),
+    })
+  }
+
+  // the instruction at PC has no implementation
+  fn unimplemented<M: MMU>(&self, mmu: &mut M) -> CpuError {
+    let pc = self.regs.pc();
+
+    CpuError::UnimplementedOpcode {
+      pc,
+      opcode: self.instruction_bytes(mmu, pc),
     }
   }
 
@@ -932,7 +1619,7 @@ impl CPU {
     self.regs.set_a(new_a);
   }
 
-  fn alu_add16imm<M: MMU>(&mut self, r: u16, mmu: &M) -> u16 {
+  fn alu_add16imm<M: MMU>(&mut self, r: u16, mmu: &mut M) -> u16 {
     let d = self.read_arg8(mmu) as u16;
 
     let v = r.wrapping_add(d);
@@ -945,49 +1632,157 @@ impl CPU {
     v
   }
 
+  // pushes the high byte first, after an internal delay
   fn push<M: MMU>(&mut self, value: u16, mmu: &mut M) {
-    self.regs.set_sp(self.regs.sp() - 2);
-    mmu.write16(self.regs.sp() as usize, value);
+    self.tick(mmu);
+
+    let sp = self.regs.sp();
+    self.write8(mmu, sp.wrapping_sub(1), (value >> 8) as u8);
+    self.write8(mmu, sp.wrapping_sub(2), value as u8);
+    self.regs.set_sp(sp.wrapping_sub(2));
   }
 
   fn pop<M: MMU>(&mut self, mmu: &mut M) -> u16 {
-    let v = mmu.read16(self.regs.sp() as usize);
-    self.regs.set_sp(self.regs.sp() + 2);
+    let sp = self.regs.sp();
+    let lo = self.read8(mmu, sp);
+    let hi = self.read8(mmu, sp.wrapping_add(1));
+    self.regs.set_sp(sp.wrapping_add(2));
 
-    v
+    ((hi as u16) << 8) | lo as u16
   }
 
-  fn read_arg8<M: MMU>(&self, mmu: &M) -> u8 {
+  fn read_arg8<M: MMU>(&mut self, mmu: &mut M) -> u8 {
     let pc = self.regs.read16(PC);
 
-    mmu.read8((pc + 1) as usize)
+    self.fetch_code8(mmu, pc.wrapping_add(1), 1, cdl::EXEC_OPERAND)
   }
 
-  fn read_arg16<M: MMU>(&self, mmu: &M) -> u16 {
+  fn read_arg16<M: MMU>(&mut self, mmu: &mut M) -> u16 {
     let pc = self.regs.read16(PC);
+    let lo = self.fetch_code8(mmu, pc.wrapping_add(1), 1, cdl::EXEC_OPERAND);
+    let hi = self.fetch_code8(mmu, pc.wrapping_add(2), 2, cdl::EXEC_OPERAND);
 
-    mmu.read16((pc + 1) as usize)
+    ((hi as u16) << 8) | lo as u16
   }
 
-  fn overflow8(&self, n1: u8, n2: u8, index: u16) -> bool {
-    self.overflow32(n1 as u32, n2 as u32, index)
+  // memory accesses take one M-cycle each, during which the rest of the
+  // system keeps running
+  fn read8<M: MMU>(&mut self, mmu: &mut M, addr: u16) -> u8 {
+    self.fetch8(mmu, addr, cdl::DATA)
   }
 
-  fn overflow16(&self, n1: u16, n2: u16, index: u16) -> bool {
-    self.overflow32(n1 as u32, n2 as u32, index)
-  }
+  // reads a byte, logging it as code or data depending on the flags
+  fn fetch8<M: MMU>(&mut self, mmu: &mut M, addr: u16, flags: u8) -> u8 {
+    self.tick(mmu);
 
-  fn overflow32(&self, n1: u32, n2: u32, index: u16) -> bool {
-    let index_mask: u32 = 1 << index + 1;
-    let mask: u32 = index_mask - 1;
+    let value = mmu.read8(addr as usize);
+    self.fetched(mmu, addr, flags, value);
 
-    ((n1 & mask) + (n2 & mask) & index_mask) == index_mask
+    value
   }
-}
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+  // reads the byte at the offset of the instruction being executed. those
+  // of an instruction from the block cache are the same as in memory, as
+  // writing there would have dropped it, so only the read itself is skipped
+  #[inline]
+  fn fetch_code8<M: MMU>(&mut self, mmu: &mut M, addr: u16, offset: usize, flags: u8) -> u8 {
+    match self.cached {
+      Some(instruction) => {
+        let value = instruction.bytes[offset];
+
+        self.tick(mmu);
+        self.fetched(mmu, addr, flags, value);
+
+        value
+      }
+      None => self.fetch8(mmu, addr, flags),
+    }
+  }
+
+  fn fetched<M: MMU>(&mut self, mmu: &M, addr: u16, flags: u8, value: u8) {
+    if let Some(cdl) = self.cdl.as_mut() {
+      if let Some(bank) = mmu.rom_bank(addr) {
+        cdl.log(bank, addr, flags);
+      }
+    }
+
+    if let Some(detector) = self.idle_loops.as_mut() {
+      detector.record(Access::Read(addr, value));
+    }
+  }
+
+  fn write8<M: MMU>(&mut self, mmu: &mut M, addr: u16, value: u8) {
+    self.tick(mmu);
+
+    self.will_write(mmu, addr);
+    mmu.write8(addr as usize, value);
+  }
+
+  // called before every write, which no idle loop can do. records
+  // the value about to be overwritten, where there is one
+  fn will_write<M: MMU>(&mut self, mmu: &M, addr: u16) {
+    if let Some(detector) = self.idle_loops.as_mut() {
+      detector.reset();
+    }
+
+    if let Some(blocks) = self.blocks.as_mut() {
+      blocks.will_write(addr);
+    }
+
+    if let Some(journal) = self.journal.as_mut() {
+      if let Some(old) = mmu.peek8(addr) {
+        journal.record_write(addr, old);
+      }
+    }
+  }
+
+  fn write16<M: MMU>(&mut self, mmu: &mut M, addr: u16, value: u16) {
+    self.write8(mmu, addr, value as u8);
+    self.write8(mmu, addr.wrapping_add(1), (value >> 8) as u8);
+  }
+
+  // an M-cycle spent without touching memory
+  fn tick<M: MMU>(&mut self, mmu: &mut M) {
+    if self.skip_ticks > 0 {
+      self.skip_ticks -= 1;
+      return;
+    }
+
+    if let Some(detector) = self.idle_loops.as_mut() {
+      detector.record(Access::Tick);
+    }
+
+    mmu.tick(self.m_cycle());
+  }
+
+  // clock cycles an M-cycle takes at the current speed
+  fn m_cycle(&self) -> u8 {
+    if self.double_speed {
+      M_CYCLE / 2
+    } else {
+      M_CYCLE
+    }
+  }
+
+  fn overflow8(&self, n1: u8, n2: u8, index: u16) -> bool {
+    self.overflow32(n1 as u32, n2 as u32, index)
+  }
+
+  fn overflow16(&self, n1: u16, n2: u16, index: u16) -> bool {
+    self.overflow32(n1 as u32, n2 as u32, index)
+  }
+
+  fn overflow32(&self, n1: u32, n2: u32, index: u16) -> bool {
+    let index_mask: u32 = 1 << index + 1;
+    let mask: u32 = index_mask - 1;
+
+    ((n1 & mask) + (n2 & mask) & index_mask) == index_mask
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
   use crate::mmu::test_mmu::TestMMU;
   use opcodes::{ExtendedOpcode::*, JumpCondition::*};
 
@@ -995,27 +1790,27 @@ mod tests {
     ($cpu:expr, $mmu:expr, $opcode:expr) => {{
       let pc = $cpu.regs.pc();
       $mmu.write8(pc as usize, 0x0);
-      $cpu.exec_opcode($opcode, pc, &mut $mmu)
+      $cpu.exec_opcode($opcode, pc, &mut $mmu).unwrap()
     }};
 
     ($cpu:expr, $mmu:expr,$opcode:expr, arg8 => $arg8:expr) => {{
       let pc = $cpu.regs.pc();
       $mmu.write8(pc as usize, 0x0);
       $mmu.write8((pc + 1) as usize, $arg8);
-      $cpu.exec_opcode($opcode, pc, &mut $mmu)
+      $cpu.exec_opcode($opcode, pc, &mut $mmu).unwrap()
     }};
 
     ($cpu:expr,$mmu:expr, $opcode:expr, arg16 => $arg16:expr) => {{
       let pc = $cpu.regs.pc();
       $mmu.write8(pc as usize, 0x0);
       $mmu.write16((pc + 1) as usize, $arg16);
-      $cpu.exec_opcode($opcode, pc, &mut $mmu)
+      $cpu.exec_opcode($opcode, pc, &mut $mmu).unwrap()
     }};
   }
 
   macro_rules! exec_cb {
     ($cpu:expr, $mmu:expr, $opcode:expr) => {{
-      $cpu.exec_cb($opcode, &mut $mmu)
+      $cpu.exec_cb($opcode, &mut $mmu).unwrap()
     }};
   }
 
@@ -2036,7 +2831,7 @@ mod tests {
     let (jump, _) = exec!(cpu, mmu, RETI);
     assert_eq!(cpu.regs.sp(), 0xff90);
     assert_eq!(jump, Some(666));
-    assert_eq!(cpu.interrupts, 1);
+    assert_eq!(cpu.ime, true);
   }
 
   #[test]
@@ -2201,13 +2996,246 @@ mod tests {
     assert_eq!(cpu.regs.sp(), 123);
   }
 
+  #[test]
+  fn opcode_stop() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    mmu.write8(0x0u16, 0b0001_0000); // STOP
+    mmu.write8(Addr::Divider, 0xAB);
+    mmu.write8(Addr::Joypad, 0x0F);
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), true);
+    assert_eq!(cpu.regs.pc(), 2);
+    assert_eq!(mmu.read8(Addr::Divider), 0);
+
+    // nothing runs until a joypad line goes low
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), true);
+    assert_eq!(cpu.regs.pc(), 2);
+
+    mmu.write8(Addr::Joypad, 0x0E);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), false);
+    assert_eq!(cpu.regs.pc(), 3);
+  }
+
+  #[test]
+  fn opcode_stop_speed_switch() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.cgb_mode = true;
+    mmu.write8(0x0u16, 0b0001_0000); // STOP
+    mmu.write8(0x2u16, 0b0001_0000); // STOP
+    mmu.write8(Addr::SpeedSwitch, 0b0000_0001);
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), false);
+    assert_eq!(cpu.double_speed(), true);
+    assert_eq!(mmu.read8(Addr::SpeedSwitch), 0b1000_0000);
+
+    // without arming KEY1 again, STOP just stops
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), true);
+    assert_eq!(cpu.double_speed(), true);
+  }
+
+  // buttons are pressed from the input thread, through a shared handle
+  #[test]
+  fn opcode_stop_real_mmu() {
+    use crate::mmu::joypad::Button;
+    use std::thread;
+
+    let mut rom = vec![0; 0x8000];
+    rom[0] = 0b0001_0000; // STOP
+    let mut mmu = crate::mmu::real_mmu::RealMMU::new(false, rom);
+    let mut cpu = CPU::new();
+    mmu.write8(Addr::Joypad, 0x20);
+
+    cpu.exec(&mut mmu).unwrap();
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), true);
+
+    let buttons = mmu.buttons();
+    let press = |button: Button| {
+      let buttons = buttons.clone();
+      thread::spawn(move || buttons.press(button)).join().unwrap();
+    };
+
+    // the action buttons aren't selected
+    press(Button::A);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), true);
+
+    press(Button::Up);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), false);
+    assert_eq!(cpu.regs.pc(), 3);
+  }
+
+  #[test]
+  fn opcode_stop_dmg_ignores_key1() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    mmu.write8(0x0u16, 0b0001_0000); // STOP
+    mmu.write8(Addr::SpeedSwitch, 0b0000_0001);
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), true);
+    assert_eq!(cpu.double_speed(), false);
+  }
+
+  #[test]
+  fn opcode_illegal() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.ime = true;
+    cpu.regs.set_pc(0x10);
+    mmu.write8(0x10u16, 0xDD);
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(
+      cpu.take_event(),
+      Some(Event::IllegalOpcode {
+        pc: 0x10,
+        opcode: 0xDD
+      })
+    );
+    assert_eq!(cpu.take_event(), None);
+    assert_eq!(cpu.regs.pc(), 0x10);
+
+    // interrupts do not wake the CPU up
+    mmu.write8(Addr::InterruptEnable, 0b0000_0001);
+    mmu.write8(Addr::InterruptFlag, 0b0000_0001);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.regs.pc(), 0x10);
+    assert_eq!(cpu.last_instr_cycles, 4);
+  }
+
+  #[test]
+  fn opcode_halt() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    mmu.write8(0x0u16, 0b0111_0110); // HALT
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.halted, true);
+    assert_eq!(cpu.regs.pc(), 1);
+
+    // no instructions are fetched while halted, but time passes
+    cpu.exec(&mut mmu).unwrap();
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.halted, true);
+    assert_eq!(cpu.regs.pc(), 1);
+    assert_eq!(cpu.last_instr_cycles, 4);
+    assert_eq!(cpu.cycles, 12);
+  }
+
+  #[test]
+  fn opcode_halt_wakes_without_ime() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    mmu.write8(0x0u16, 0b0111_0110); // HALT
+    mmu.write8(Addr::InterruptEnable, 0b0000_0001);
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.halted, true);
+
+    // with IME unset, execution resumes after HALT without dispatching
+    mmu.write8(Addr::InterruptFlag, 0b0000_0001);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.halted, false);
+    assert_eq!(cpu.regs.pc(), 2);
+    assert_eq!(mmu.read8(Addr::InterruptFlag), 0b0000_0001);
+  }
+
+  #[test]
+  fn opcode_halt_wakes_with_ime() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.ime = true;
+    cpu.regs.set_sp(0xfffe);
+    mmu.write8(0x0u16, 0b0111_0110); // HALT
+    mmu.write8(Addr::InterruptEnable, 0b0000_0001);
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.halted, true);
+
+    mmu.write8(Addr::InterruptFlag, 0b0000_0001);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.halted, false);
+    assert_eq!(cpu.regs.pc(), 0x40);
+    assert_eq!(mmu.read16(0xfffcu16), 1);
+  }
+
+  #[test]
+  fn opcode_halt_bug() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    mmu.write8(0x0u16, 0b0111_0110); // HALT
+    mmu.write8(0x1u16, 0b0011_1100); // INC A
+    mmu.write8(Addr::InterruptEnable, 0b0000_0001);
+    mmu.write8(Addr::InterruptFlag, 0b0000_0001);
+
+    // HALT with IME unset and a pending interrupt does not halt
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.halted, false);
+    assert_eq!(cpu.regs.pc(), 1);
+
+    // but the following byte is executed twice
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.regs.pc(), 1);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.regs.pc(), 2);
+    assert_eq!(cpu.regs.a(), 2);
+  }
+
+  #[test]
+  fn opcode_halt_bug_operand() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    mmu.write8(0x0u16, 0b0111_0110); // HALT
+    mmu.write8(0x1u16, 0b0011_1110); // LD A, n
+    mmu.write8(0x2u16, 0x14);
+    mmu.write8(Addr::InterruptEnable, 0b0000_0001);
+    mmu.write8(Addr::InterruptFlag, 0b0000_0001);
+
+    cpu.exec(&mut mmu).unwrap();
+    cpu.exec(&mut mmu).unwrap();
+
+    // the opcode byte is read again as the operand
+    assert_eq!(cpu.regs.a(), 0b0011_1110);
+    assert_eq!(cpu.regs.pc(), 2);
+  }
+
+  #[test]
+  fn opcode_halt_bug_ei() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.regs.set_sp(0xfffe);
+    mmu.write8(0x0u16, 0b1111_1011); // EI
+    mmu.write8(0x1u16, 0b0111_0110); // HALT
+    mmu.write8(0x40u16, 0b0011_1100); // INC A
+    mmu.write8(0x41u16, 0b1101_1001); // RETI
+    mmu.write8(Addr::InterruptEnable, 0b0000_0001);
+    mmu.write8(Addr::InterruptFlag, 0b0000_0001);
+
+    cpu.exec(&mut mmu).unwrap();
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.halted, false);
+
+    // the interrupt is dispatched, returning to HALT
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.regs.pc(), 0x40);
+    assert_eq!(mmu.read16(0xfffcu16), 1);
+
+    // and its handler runs only once
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.regs.pc(), 0x41);
+    assert_eq!(cpu.regs.a(), 1);
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.regs.pc(), 1);
+  }
+
   #[test]
   fn opcode_di() {
     let (mut cpu, mut mmu) = new_test_cpu();
 
+    cpu.ime = true;
     exec!(cpu, mmu, DI);
 
-    assert_eq!(cpu.interrupts, 0);
+    assert_eq!(cpu.ime, false);
   }
 
   #[test]
@@ -2216,7 +3244,107 @@ mod tests {
 
     exec!(cpu, mmu, EI);
 
-    assert_eq!(cpu.interrupts, 1);
+    assert_eq!(cpu.ime, false);
+    assert_eq!(cpu.ime_scheduled, true);
+  }
+
+  #[test]
+  fn ei_enables_interrupts_after_next_instruction() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    mmu.write8(0x0u16, 0b1111_1011); // EI
+    mmu.write8(0x1u16, 0x0); // NOP
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.ime, false);
+
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.ime, true);
+  }
+
+  #[test]
+  fn di_after_ei_cancels_enable() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    mmu.write8(0x0u16, 0b1111_1011); // EI
+    mmu.write8(0x1u16, 0b1111_0011); // DI
+
+    cpu.exec(&mut mmu).unwrap();
+    cpu.exec(&mut mmu).unwrap();
+
+    assert_eq!(cpu.ime, false);
+  }
+
+  #[test]
+  fn interrupt_dispatch() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.ime = true;
+    cpu.regs.set_pc(0x1234);
+    cpu.regs.set_sp(0xfffe);
+    mmu.write8(Addr::InterruptEnable, 0b0000_0100);
+    mmu.write8(Addr::InterruptFlag, 0b0000_0100);
+
+    cpu.exec(&mut mmu).unwrap();
+
+    assert_eq!(cpu.regs.pc(), 0x50);
+    assert_eq!(cpu.regs.sp(), 0xfffc);
+    assert_eq!(mmu.read16(0xfffcu16), 0x1234);
+    assert_eq!(mmu.read8(Addr::InterruptFlag), 0);
+    assert_eq!(cpu.ime, false);
+    assert_eq!(cpu.last_instr_cycles, 20);
+  }
+
+  #[test]
+  fn interrupt_dispatch_priority() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.ime = true;
+    cpu.regs.set_sp(0xfffe);
+    mmu.write8(Addr::InterruptEnable, 0b0001_1111);
+    mmu.write8(Addr::InterruptFlag, 0b0001_0010);
+
+    cpu.exec(&mut mmu).unwrap();
+
+    assert_eq!(cpu.regs.pc(), 0x48);
+    assert_eq!(mmu.read8(Addr::InterruptFlag), 0b0001_0000);
+  }
+
+  #[test]
+  fn interrupt_not_dispatched() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+
+    // IME not set
+    mmu.write8(Addr::InterruptEnable, 0b0000_0001);
+    mmu.write8(Addr::InterruptFlag, 0b0000_0001);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.regs.pc(), 1);
+    assert_eq!(mmu.read8(Addr::InterruptFlag), 0b0000_0001);
+
+    // interrupt requested but not enabled
+    cpu.ime = true;
+    mmu.write8(Addr::InterruptEnable, 0b0000_0010);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.regs.pc(), 2);
+  }
+
+  #[test]
+  fn watched_internal_reads() {
+    use crate::debug::watchpoints::{Trigger, Watched, Watchpoint, Watchpoints};
+
+    let mut watchpoints = Watchpoints::new();
+    watchpoints.add(Watchpoint::new(0x0000, 0xFFFF, Trigger::Read));
+
+    let (mut cpu, mut mmu) = new_test_cpu();
+    mmu.write8(0x0u16, 0b0001_0000); // STOP
+    mmu.write8(Addr::Joypad, 0x0F);
+    cpu.ime = true;
+    let mut mmu = Watched::new(mmu, &watchpoints, 0);
+
+    // fetching STOP is the program's own read
+    cpu.exec(&mut mmu).unwrap();
+    assert!(mmu.take_hit().is_some());
+
+    // polling the joypad and checking for interrupts aren't
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.stopped(), true);
+    assert_eq!(mmu.take_hit(), None);
   }
 
   #[test]
@@ -2406,4 +3534,540 @@ mod tests {
     exec_cb!(cpu, mmu, SET(2, PtrReg16(HL)));
     assert_eq!(mmu.read8(0xff90u16), 0b0000_0100);
   }
+
+  // runs a single instruction at $C000, returning the clock cycles
+  // ticked on the MMU along with the ones reported by the CPU
+  fn ticked_cycles(bytes: &[u8], flags: u8) -> (u32, u8) {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.regs.set_af(flags as u16);
+    cpu.regs.set_pc(0xC000);
+    cpu.regs.set_sp(0xD000);
+
+    for (i, &byte) in bytes.iter().enumerate() {
+      mmu.write8(0xC000 + i, byte);
+    }
+
+    cpu.exec(&mut mmu).unwrap();
+
+    (mmu.cycles, cpu.last_instr_cycles)
+  }
+
+  #[test]
+  fn ticks_match_cycles() {
+    for byte in 0..=0xFFu8 {
+      // branches are taken with all flags set or cleared
+      for &flags in &[0x00, 0xF0] {
+        let (ticked, cycles) = ticked_cycles(&[byte, 0x00, 0xC0], flags);
+        assert_eq!(
+          ticked, cycles as u32,
+          "opcode {:#04x}, flags {:#04x}",
+          byte, flags
+        );
+      }
+    }
+
+    for byte in 0..=0xFFu8 {
+      let (ticked, cycles) = ticked_cycles(&[0xCB, byte], 0);
+      assert_eq!(ticked, cycles as u32, "opcode $CB {:#04x}", byte);
+    }
+  }
+
+  #[test]
+  fn ticks_instruction_cycles() {
+    // DEC B
+    assert_eq!(ticked_cycles(&[0x05], 0), (4, 4));
+    // JP NZ, a16
+    assert_eq!(ticked_cycles(&[0xC2, 0x00, 0xC0], 0x00), (16, 16));
+    assert_eq!(ticked_cycles(&[0xC2, 0x00, 0xC0], 0x80), (12, 12));
+    // JP a16
+    assert_eq!(ticked_cycles(&[0xC3, 0x00, 0xC0], 0), (16, 16));
+    // LD (a16), SP
+    assert_eq!(ticked_cycles(&[0x08, 0x00, 0xC1], 0), (20, 20));
+  }
+
+  #[test]
+  fn ticks_half_as_many_in_double_speed() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.double_speed = true;
+
+    // LD (a16), SP
+    mmu.write8(0x0000u16, 0x08);
+    mmu.write8(0x0001u16, 0x00);
+    mmu.write8(0x0002u16, 0xC1);
+    mmu.writes.clear();
+    cpu.exec(&mut mmu).unwrap();
+
+    assert_eq!(cpu.last_instr_cycles, 20);
+    assert_eq!(mmu.cycles, 10);
+    assert_eq!(mmu.writes, vec![(8, 0xC100), (10, 0xC101)]);
+  }
+
+  #[test]
+  fn ticks_memory_access_timing() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+
+    // INC (HL) reads on the second M-cycle and writes on the third,
+    // each access ticking its M-cycle first
+    cpu.regs.set_hl(0xC100);
+    mmu.write8(0x0000u16, 0x34);
+    mmu.writes.clear();
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(mmu.writes, vec![(12, 0xC100)]);
+
+    // PUSH BC writes the high byte first, after an internal delay
+    cpu.regs.set_sp(0xD000);
+    mmu.write8(0x0001u16, 0xC5);
+    mmu.writes.clear();
+    mmu.cycles = 0;
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(mmu.writes, vec![(12, 0xCFFF), (16, 0xCFFE)]);
+  }
+
+  #[test]
+  fn exec_step_info() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.regs.set_pc(0x0150);
+    mmu.write8(0x0150u16, 0x3C);
+
+    assert_eq!(
+      cpu.exec(&mut mmu),
+      Ok(StepInfo {
+        pc: 0x0150,
+        cycles: 4,
+        skipped: 0
+      })
+    );
+  }
+
+  #[test]
+  fn exec_unsupported_access() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.regs.set_pc(0x0150);
+    mmu.write8(0x0150u16, 0xEA);
+    mmu.write16(0x0151u16, 0x2000);
+    mmu.fault = Some(Fault::Write(0x2000));
+
+    let error = cpu.exec(&mut mmu).unwrap_err();
+
+    assert_eq!(
+      error,
+      CpuError::UnsupportedWrite {
+        pc: 0x0150,
+        opcode: vec![0xEA, 0x00, 0x20],
+        address: 0x2000
+      }
+    );
+    assert_eq!(
+      error.to_string(),
+      "Unsupported write to 0x2000 by EA 00 20 at 0x0150"
+    );
+  }
+
+  #[test]
+  fn snapshot_restore() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.regs.set_pc(0xC000);
+    cpu.regs.set_sp(0xD000);
+    mmu.write8(0xC000u16, 0xFB);
+    mmu.write8(0xC001u16, 0xC5);
+    mmu.write8(0xC002u16, 0x76);
+    cpu.exec(&mut mmu).unwrap();
+
+    let snapshot = cpu.snapshot();
+    cpu.exec(&mut mmu).unwrap();
+    cpu.exec(&mut mmu).unwrap();
+    let after = cpu.snapshot();
+
+    let mut restored = CPU::new();
+    restored.restore(&snapshot);
+    assert_eq!(restored.snapshot(), snapshot);
+    assert_eq!(restored.ime_scheduled, true);
+
+    restored.exec(&mut mmu).unwrap();
+    restored.exec(&mut mmu).unwrap();
+    assert_eq!(restored.snapshot(), after);
+    assert_eq!(restored.halted, true);
+  }
+
+  #[test]
+  fn profile() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.set_profiler(Profiler::new());
+    cpu.regs.set_pc(0xC000);
+    cpu.regs.set_sp(0xD000);
+
+    // a loop calling a routine: call $C010 / jr $C000, with the routine
+    // at $C010 being a single ret
+    mmu.write8(0xC000u16, 0xCD);
+    mmu.write16(0xC001u16, 0xC010);
+    mmu.write8(0xC003u16, 0x18);
+    mmu.write8(0xC004u16, 0xFB);
+    mmu.write8(0xC010u16, 0xC9);
+
+    for _ in 0..6 {
+      cpu.exec(&mut mmu).unwrap();
+    }
+
+    let profiler = cpu.take_profiler().unwrap();
+    assert_eq!(profiler.instruction(None, 0xC000).executions, 2);
+    assert_eq!(profiler.instruction(None, 0xC010).cycles, 32);
+    assert_eq!(profiler.calls(None, 0xC010), 2);
+
+    let mut out = Vec::new();
+    profiler.report(&mut out, 1).unwrap();
+    assert!(String::from_utf8(out)
+      .unwrap()
+      .contains("--:C000-C003           2\n"));
+  }
+
+  #[test]
+  fn call_stack() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.regs.set_pc(0xC000);
+    cpu.regs.set_sp(0xD000);
+    cpu.ime = true;
+
+    // call $C010, with the routine at $C010 being call $C020 / ret,
+    // and the one at $C020 a single ret
+    mmu.write8(0xC000u16, 0xCD);
+    mmu.write16(0xC001u16, 0xC010);
+    mmu.write8(0xC010u16, 0xCD);
+    mmu.write16(0xC011u16, 0xC020);
+    mmu.write8(0xC013u16, 0xC9);
+    mmu.write8(0xC020u16, 0xC9);
+
+    cpu.exec(&mut mmu).unwrap();
+    cpu.exec(&mut mmu).unwrap();
+
+    let kinds: Vec<_> = cpu.call_stack().frames().iter().map(|f| f.kind).collect();
+    assert_eq!(kinds, [FrameKind::Call, FrameKind::Call]);
+    assert_eq!(cpu.call_stack().frames()[1].caller, 0xC010);
+    assert_eq!(cpu.call_stack().frames()[1].sp, 0xCFFC);
+
+    // an interrupt fires right before the innermost ret
+    mmu.write8(Addr::InterruptEnable, 0x01);
+    mmu.write8(Addr::InterruptFlag, 0x01);
+    mmu.write8(0x0040u16, 0xD9);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.call_stack().frames()[2].kind, FrameKind::Interrupt);
+    assert_eq!(cpu.call_stack().frames()[2].caller, 0xC020);
+
+    for remaining in &[2, 1, 0] {
+      cpu.exec(&mut mmu).unwrap();
+      assert_eq!(cpu.call_stack().frames().len(), *remaining);
+    }
+    assert_eq!(cpu.regs.pc(), 0xC003);
+
+    // rst $38
+    mmu.write8(0xC003u16, 0xFF);
+    cpu.exec(&mut mmu).unwrap();
+    assert_eq!(cpu.call_stack().frames()[0].kind, FrameKind::Rst);
+    assert_eq!(cpu.call_stack().frames()[0].target, 0x0038);
+  }
+
+  #[test]
+  fn step_back() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.set_journal(Journal::new(16));
+    cpu.regs.set_pc(0xC000);
+    cpu.regs.set_sp(0xD000);
+
+    // ld a, $05 / ld [$C100], a / inc a / push af / ld [$C100], a
+    let program = [0x3E, 0x05, 0xEA, 0x00, 0xC1, 0x3C, 0xF5, 0xEA, 0x00, 0xC1];
+    for (i, &byte) in program.iter().enumerate() {
+      mmu.write8(0xC000 + i, byte);
+    }
+
+    for _ in 0..5 {
+      cpu.exec(&mut mmu).unwrap();
+    }
+    assert_eq!(mmu.read8(0xC100u16), 6);
+    assert_eq!(mmu.read8(0xCFFFu16), 6);
+
+    assert_eq!(cpu.step_back_to_write(&mut mmu, 0xC100), true);
+    assert_eq!((cpu.regs.pc(), cpu.regs.a()), (0xC007, 6));
+    assert_eq!(mmu.read8(0xC100u16), 5);
+
+    assert_eq!(cpu.step_back(&mut mmu), true);
+    assert_eq!((cpu.regs.pc(), cpu.regs.sp()), (0xC006, 0xD000));
+    assert_eq!(mmu.read8(0xCFFFu16), 0);
+
+    assert_eq!(cpu.step_back_to_write(&mut mmu, 0xC100), true);
+    assert_eq!((cpu.regs.pc(), cpu.regs.a()), (0xC002, 5));
+    assert_eq!(mmu.read8(0xC100u16), 0);
+
+    assert_eq!(cpu.step_back_to_write(&mut mmu, 0xC100), false);
+    assert_eq!(cpu.step_back(&mut mmu), true);
+    assert_eq!(cpu.regs.pc(), 0xC000);
+    assert_eq!(cpu.step_back(&mut mmu), false);
+  }
+
+  #[test]
+  fn step_back_call_stack() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.set_journal(Journal::new(16));
+    cpu.regs.set_pc(0xC000);
+    cpu.regs.set_sp(0xD000);
+
+    // call $C010, with a ret at $C010
+    mmu.write8(0xC000u16, 0xCD);
+    mmu.write16(0xC001u16, 0xC010);
+    mmu.write8(0xC010u16, 0xC9);
+
+    cpu.exec(&mut mmu).unwrap();
+    cpu.exec(&mut mmu).unwrap();
+    assert!(cpu.call_stack().frames().is_empty());
+
+    assert_eq!(cpu.step_back(&mut mmu), true);
+    assert_eq!(cpu.call_stack().frames()[0].target, 0xC010);
+
+    assert_eq!(cpu.step_back(&mut mmu), true);
+    assert!(cpu.call_stack().frames().is_empty());
+  }
+
+  // a loop written with asm!, multiplying b by c into a
+  #[test]
+  fn asm_multiply() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.regs.set_pc(0xC000);
+
+    let program = crate::asm!(
+      "  ld b, 6
+         ld c, 7
+         xor a
+       loop:
+         add a, c
+         dec b
+         jr nz, loop
+         halt",
+      0xC000
+    );
+    load(&mut mmu, 0xC000, &program);
+
+    while !cpu.halted {
+      cpu.exec(&mut mmu).unwrap();
+    }
+
+    assert_eq!(cpu.regs.a(), 42);
+    assert_eq!(cpu.regs.pc(), 0xC00A);
+  }
+
+  fn load(mmu: &mut TestMMU, address: usize, bytes: &[u8]) {
+    for (i, &byte) in bytes.iter().enumerate() {
+      mmu.write8(address + i, byte);
+    }
+  }
+
+  // runs until the instruction at `pc` has executed `times` times,
+  // returning the cycles ticked by then along with those skipped
+  fn run_to(cpu: &mut CPU, mmu: &mut TestMMU, pc: u16, times: usize) -> (Vec<u32>, u32) {
+    let mut reached = Vec::new();
+    let mut skipped = 0;
+
+    while reached.len() < times {
+      let info = cpu.exec(mmu).unwrap();
+      skipped += info.skipped;
+
+      if info.pc == pc {
+        reached.push(mmu.cycles);
+      }
+    }
+
+    (reached, skipped)
+  }
+
+  #[test]
+  fn idle_loop_polling_ly() {
+    // LY advancing every line as on hardware
+    fn ly(mmu: &mut TestMMU) {
+      let line = (mmu.cycles / 456 % 154) as u8;
+      mmu.write8(Addr::CurrentScanLine, line);
+    }
+
+    let run = |detect: bool| {
+      let (mut cpu, mut mmu) = new_test_cpu();
+      cpu.set_idle_loop_detection(detect);
+      cpu.regs.set_pc(0xC000);
+      mmu.on_tick = Some(ly);
+
+      // ldh a, [$44] / cp $90 / jr nz, $C000 / ld [$C100], a / jr $C000
+      load(
+        &mut mmu,
+        0xC000,
+        &[
+          0xF0, 0x44, 0xFE, 0x90, 0x20, 0xFA, 0xEA, 0x00, 0xC1, 0x18, 0xF5,
+        ],
+      );
+
+      let (reached, skipped) = run_to(&mut cpu, &mut mmu, 0xC006, 3);
+      (reached, skipped, cpu.regs.clone())
+    };
+
+    let (reached, skipped, regs) = run(true);
+    let (expected, _, expected_regs) = run(false);
+
+    assert!(skipped > 0);
+    assert_eq!(reached, expected);
+    assert_eq!(regs, expected_regs);
+  }
+
+  #[test]
+  fn idle_loop_polling_interrupt_flag() {
+    // a VBlank interrupt requested once
+    fn vblank(mmu: &mut TestMMU) {
+      if mmu.cycles == 10_000 {
+        mmu.set_flag(Addr::InterruptFlag, Interrupt::VBlank);
+      }
+    }
+
+    let run = |detect: bool| {
+      let (mut cpu, mut mmu) = new_test_cpu();
+      cpu.set_idle_loop_detection(detect);
+      cpu.regs.set_pc(0xC000);
+      cpu.regs.set_sp(0xD000);
+      cpu.ime = true;
+      mmu.write8(Addr::InterruptEnable, 0x01);
+      mmu.on_tick = Some(vblank);
+
+      // ld a, [$C100] / and a / jr z, $C000 / jr $C006, with the handler
+      // being ld a, 1 / ld [$C100], a / reti
+      load(
+        &mut mmu,
+        0xC000,
+        &[0xFA, 0x00, 0xC1, 0xA7, 0x28, 0xFA, 0x18, 0xFE],
+      );
+      load(&mut mmu, 0x0040, &[0x3E, 0x01, 0xEA, 0x00, 0xC1, 0xD9]);
+
+      let (reached, skipped) = run_to(&mut cpu, &mut mmu, 0xC006, 1);
+      (reached, skipped, cpu.regs.clone())
+    };
+
+    let (reached, skipped, regs) = run(true);
+    let (expected, _, expected_regs) = run(false);
+
+    assert!(skipped > 0);
+    assert_eq!(reached, expected);
+    assert_eq!(regs, expected_regs);
+  }
+
+  // an idle loop is skipped for a frame's worth of clock cycles at most,
+  // even as they pass twice as slowly for the CPU in double speed
+  #[test]
+  fn idle_loop_double_speed() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.set_idle_loop_detection(true);
+    cpu.double_speed = true;
+    cpu.regs.set_pc(0xC000);
+
+    // ld a, [$C100] / and a / jr z, $C000
+    load(&mut mmu, 0xC000, &[0xFA, 0x00, 0xC1, 0xA7, 0x28, 0xFA]);
+
+    let (skipped, ticked) = loop {
+      let before = mmu.cycles;
+      let info = cpu.exec(&mut mmu).unwrap();
+
+      if info.skipped > 0 {
+        break (info.skipped, mmu.cycles - before);
+      }
+    };
+
+    assert!(skipped >= MAX_SKIPPED_CYCLES);
+    assert!(skipped < MAX_SKIPPED_CYCLES + 100);
+    assert!(ticked >= skipped);
+  }
+
+  // runs a program with and without the block cache side by side, checking
+  // that both are in the same state after every step. returns the number
+  // of blocks cached by then
+  fn block_cache_lockstep(program: &[u8], steps: usize) -> usize {
+    let mut cpus = [CPU::new(), CPU::new()];
+    let mut mmus = [TestMMU::new(), TestMMU::new()];
+    cpus[1].set_block_cache(true);
+
+    for (cpu, mmu) in cpus.iter_mut().zip(mmus.iter_mut()) {
+      cpu.regs.set_pc(0xC000);
+      cpu.regs.set_sp(0xD000);
+      load(mmu, 0xC000, program);
+    }
+
+    for step in 0..steps {
+      let results: Vec<_> = cpus
+        .iter_mut()
+        .zip(mmus.iter_mut())
+        .map(|(cpu, mmu)| cpu.exec(mmu))
+        .collect();
+
+      assert_eq!(results[0], results[1], "step {}", step);
+      assert_eq!(cpus[0].snapshot(), cpus[1].snapshot(), "step {}", step);
+      assert_eq!(mmus[0].cycles, mmus[1].cycles, "step {}", step);
+      assert_eq!(mmus[0].writes, mmus[1].writes, "step {}", step);
+
+      if results[0].is_err() {
+        break;
+      }
+    }
+
+    for &(_, address) in &mmus[0].writes {
+      assert_eq!(mmus[0].read8(address), mmus[1].read8(address));
+    }
+
+    cpus[1].blocks.as_ref().unwrap().len()
+  }
+
+  #[test]
+  fn block_cache_self_modifying_code() {
+    // ld hl, $C008 / inc [hl] / nop / nop / nop / ld a, $00 /
+    // ld [$C100], a / jr $C000, with inc bumping the operand of ld a
+    let program = [
+      0x21, 0x08, 0xC0, 0x34, 0x00, 0x00, 0x00, 0x3E, 0x00, 0xEA, 0x00, 0xC1, 0x18, 0xF2,
+    ];
+
+    assert!(block_cache_lockstep(&program, 1000) > 0);
+  }
+
+  #[test]
+  fn block_cache_random_programs() {
+    let mut seed: u32 = 0x1234_5678;
+
+    for _ in 0..500 {
+      let program: Vec<u8> = (0..64)
+        .map(|_| {
+          seed ^= seed << 13;
+          seed ^= seed >> 17;
+          seed ^= seed << 5;
+          seed as u8
+        })
+        .collect();
+
+      block_cache_lockstep(&program, 200);
+    }
+  }
+
+  #[test]
+  fn code_data_logger() {
+    let (mut cpu, mut mmu) = new_test_cpu();
+    cpu.set_code_data_logger(cdl::Logger::new(0x8000));
+
+    // ld a, [$0150] / bit 7, a
+    mmu.write8(0x0000u16, 0xFA);
+    mmu.write16(0x0001u16, 0x0150);
+    mmu.write8(0x0003u16, 0xCB);
+    mmu.write8(0x0004u16, 0x7F);
+    cpu.exec(&mut mmu).unwrap();
+    cpu.exec(&mut mmu).unwrap();
+
+    let logger = cpu.take_code_data_logger().unwrap();
+    assert_eq!(
+      logger.flags()[0x0000..0x0006],
+      [
+        cdl::EXEC_FIRST,
+        cdl::EXEC_OPERAND,
+        cdl::EXEC_OPERAND,
+        cdl::EXEC_FIRST,
+        cdl::EXEC_OPERAND,
+        0
+      ]
+    );
+    assert_eq!(logger.flags()[0x0150], cdl::DATA);
+  }
 }
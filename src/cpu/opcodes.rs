@@ -28,6 +28,7 @@ pub enum Opcode {
   DI,
   EI,
   CALLBACK,
+  ILLEGAL(u8),
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -148,6 +149,7 @@ pub fn decode(byte: u8) -> Opcode {
     0b1111_0011 => DI,
     0b1111_1011 => EI,
     0b11001011 => CALLBACK,
+    0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => ILLEGAL(byte),
     _ => unreachable!("Invalid opcode {:#02b}", byte),
   }
 }
@@ -177,16 +179,61 @@ pub fn op_size(opcode: Opcode) -> u16 {
     JUMP(_, Imm8) => 2,
     ALU(_, _, Imm8) => 2,
     JUMP(_, Addr16) => 3,
+    CALL(_, Addr16) => 3,
     ADD(_, Imm8) => 2,
     LD(_, SPPlusImm8) => 2,
     LD(HighMemImm8, _) => 2,
     LD(_, HighMemImm8) => 2,
     LD(_, Addr16) => 3,
     CALLBACK => 2,
+    STOP => 2,
     _ => 1,
   }
 }
 
+// decoding results for every byte, precomputed from `decode`,
+// `decode_extended` and `op_size`, so that executing an instruction
+// doesn't need to go through the decoders every time
+pub struct DecodeTable {
+  opcodes: [(Opcode, u16); 256],
+  extended: [ExtendedOpcode; 256],
+}
+
+impl DecodeTable {
+  pub fn new() -> DecodeTable {
+    let mut table = DecodeTable {
+      opcodes: [(NOP, 1); 256],
+      extended: [RLC(Reg8(B)); 256],
+    };
+
+    for byte in 0..=255u8 {
+      let opcode = decode(byte);
+
+      table.opcodes[byte as usize] = (opcode, op_size(opcode));
+      table.extended[byte as usize] = decode_extended(byte);
+    }
+
+    table
+  }
+
+  // the opcode for the given byte, along with its size
+  #[inline]
+  pub fn decode(&self, byte: u8) -> (Opcode, u16) {
+    self.opcodes[byte as usize]
+  }
+
+  #[inline]
+  pub fn decode_extended(&self, byte: u8) -> ExtendedOpcode {
+    self.extended[byte as usize]
+  }
+}
+
+impl Default for DecodeTable {
+  fn default() -> DecodeTable {
+    DecodeTable::new()
+  }
+}
+
 fn reg16(byte: u8, column: usize, index: usize) -> registers::Register16 {
   let row = (byte >> (8 - index - 2)) & 0b0011;
 
@@ -372,6 +419,11 @@ mod test {
     assert_decode!(0b0001_0000, STOP);
   }
 
+  #[test]
+  fn stop_size() {
+    assert_eq!(op_size(STOP), 2);
+  }
+
   #[test]
   fn j_r_n() {
     assert_decode!(0b0001_1000, JUMP(Always, Imm8));
@@ -598,6 +650,12 @@ mod test {
     assert_decode!(0b110_01_101, CALL(Always, Addr16));
   }
 
+  #[test]
+  fn call_size() {
+    assert_eq!(op_size(CALL(Always, Addr16)), 3);
+    assert_eq!(op_size(CALL(NotZero, Addr16)), 3);
+  }
+
   #[test]
   fn add_sp_n() {
     assert_decode!(0b111_01000, ADD(Reg16(SP), Imm8));
@@ -663,6 +721,33 @@ mod test {
     assert_decode!(0b1100_1011, CALLBACK);
   }
 
+  #[test]
+  fn illegal() {
+    assert_decode!(0xD3, ILLEGAL(0xD3));
+    assert_decode!(0xDB, ILLEGAL(0xDB));
+    assert_decode!(0xDD, ILLEGAL(0xDD));
+    assert_decode!(0xE3, ILLEGAL(0xE3));
+    assert_decode!(0xE4, ILLEGAL(0xE4));
+    assert_decode!(0xEB, ILLEGAL(0xEB));
+    assert_decode!(0xEC, ILLEGAL(0xEC));
+    assert_decode!(0xED, ILLEGAL(0xED));
+    assert_decode!(0xF4, ILLEGAL(0xF4));
+    assert_decode!(0xFC, ILLEGAL(0xFC));
+    assert_decode!(0xFD, ILLEGAL(0xFD));
+  }
+
+  #[test]
+  fn decode_table() {
+    let table = DecodeTable::new();
+
+    for byte in 0..=255u8 {
+      let opcode = decode(byte);
+
+      assert_eq!(table.decode(byte), (opcode, op_size(opcode)));
+      assert_eq!(table.decode_extended(byte), decode_extended(byte));
+    }
+  }
+
   #[test]
   fn rlc_d() {
     assert_decode_callback!(0b00000_000, RLC(Reg8(B)));
@@ -0,0 +1,176 @@
+// execution profile of a run: how often each instruction was executed and
+// the cycles it took, how often each routine was called, and how many times
+// each loop went around
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// ROM bank, if in ROM, and address
+type Location = (Option<u8>, u16);
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Counter {
+  pub executions: u64,
+  pub cycles: u64,
+}
+
+pub struct Profiler {
+  instructions: HashMap<Location, Counter>,
+  calls: HashMap<Location, u64>,
+  // keyed by the start of the loop, the target of the backward jump,
+  // along with the address of the jump
+  loops: HashMap<(Location, u16), u64>,
+  cycles: u64,
+}
+
+impl Profiler {
+  pub fn new() -> Profiler {
+    Profiler {
+      instructions: HashMap::new(),
+      calls: HashMap::new(),
+      loops: HashMap::new(),
+      cycles: 0,
+    }
+  }
+
+  pub fn record(&mut self, bank: Option<u8>, pc: u16, cycles: u8) {
+    let counter = self.instructions.entry((bank, pc)).or_default();
+    counter.executions += 1;
+    counter.cycles += cycles as u64;
+    self.cycles += cycles as u64;
+  }
+
+  pub fn record_call(&mut self, bank: Option<u8>, target: u16) {
+    *self.calls.entry((bank, target)).or_default() += 1;
+  }
+
+  pub fn record_loop(&mut self, bank: Option<u8>, target: u16, pc: u16) {
+    *self.loops.entry(((bank, target), pc)).or_default() += 1;
+  }
+
+  pub fn instruction(&self, bank: Option<u8>, pc: u16) -> Counter {
+    self
+      .instructions
+      .get(&(bank, pc))
+      .cloned()
+      .unwrap_or_default()
+  }
+
+  pub fn calls(&self, bank: Option<u8>, target: u16) -> u64 {
+    self.calls.get(&(bank, target)).cloned().unwrap_or(0)
+  }
+
+  // writes the `limit` hottest instructions, loops and routines
+  pub fn report<W: Write>(&self, out: &mut W, limit: usize) -> io::Result<()> {
+    let mut instructions: Vec<_> = self.instructions.iter().collect();
+    instructions.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+
+    writeln!(out, "; hottest instructions, of {} cycles", self.cycles)?;
+    writeln!(out, "; location   executions       cycles      %")?;
+
+    for (&location, counter) in instructions.iter().take(limit) {
+      writeln!(
+        out,
+        "{}  {:>10}  {:>11}  {:>5.1}",
+        format_location(location),
+        counter.executions,
+        counter.cycles,
+        counter.cycles as f64 * 100.0 / self.cycles as f64
+      )?;
+    }
+
+    let mut loops: Vec<_> = self.loops.iter().collect();
+    loops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+    writeln!(out)?;
+    writeln!(out, "; hottest loops")?;
+    writeln!(out, "; location        iterations")?;
+
+    for (&(start, end), iterations) in loops.iter().take(limit) {
+      writeln!(
+        out,
+        "{}-{:04X}  {:>10}",
+        format_location(start),
+        end,
+        iterations
+      )?;
+    }
+
+    let mut calls: Vec<_> = self.calls.iter().collect();
+    calls.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+    writeln!(out)?;
+    writeln!(out, "; most called routines")?;
+    writeln!(out, "; location        calls")?;
+
+    for (&location, calls) in calls.iter().take(limit) {
+      writeln!(out, "{}  {:>11}", format_location(location), calls)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Default for Profiler {
+  fn default() -> Profiler {
+    Profiler::new()
+  }
+}
+
+fn format_location((bank, address): Location) -> String {
+  match bank {
+    Some(bank) => format!("{:02X}:{:04X}", bank, address),
+    None => format!("--:{:04X}", address),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn report(profiler: &Profiler) -> String {
+    let mut out = Vec::new();
+    profiler.report(&mut out, 10).unwrap();
+
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn record() {
+    let mut profiler = Profiler::new();
+    profiler.record(Some(0), 0x0150, 4);
+    profiler.record(Some(0), 0x0150, 4);
+    profiler.record(None, 0xC000, 12);
+
+    assert_eq!(
+      profiler.instruction(Some(0), 0x0150),
+      Counter {
+        executions: 2,
+        cycles: 8
+      }
+    );
+    assert_eq!(profiler.instruction(Some(1), 0x0150), Counter::default());
+  }
+
+  #[test]
+  fn report_sorted() {
+    let mut profiler = Profiler::new();
+    profiler.record(Some(0), 0x0150, 4);
+    profiler.record(None, 0xC000, 12);
+    profiler.record_call(Some(1), 0x4000);
+    profiler.record_call(Some(1), 0x4000);
+    profiler.record_call(Some(0), 0x0200);
+    profiler.record_loop(Some(0), 0x0150, 0x0158);
+
+    let out = report(&profiler);
+
+    assert!(out.contains(
+      "; hottest instructions, of 16 cycles\n\
+       ; location   executions       cycles      %\n\
+       --:C000           1           12   75.0\n\
+       00:0150           1            4   25.0\n"
+    ));
+    assert!(out.contains("00:0150-0158           1\n"));
+    assert!(out.contains("01:4000            2\n00:0200            1\n"));
+  }
+}
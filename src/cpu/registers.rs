@@ -1,3 +1,5 @@
+use std::fmt;
+
 type Reg8x2 = (u8, u8);
 type Reg16 = u16;
 
@@ -29,7 +31,7 @@ pub enum Register16 {
   AF,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 #[allow(non_snake_case)]
 pub struct Registers {
   AF: Reg8x2,
@@ -208,6 +210,21 @@ impl Registers {
   }
 }
 
+impl fmt::Display for Registers {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "AF:{:04X} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} PC:{:04X}",
+      self.af(),
+      self.bc(),
+      self.de(),
+      self.hl(),
+      self.sp(),
+      self.pc()
+    )
+  }
+}
+
 fn reg8x2_to_reg16(reg: Reg8x2) -> Reg16 {
   (reg.1 as u16) | ((reg.0 as u16) << 8)
 }
@@ -352,4 +369,18 @@ mod tests {
     registers.set_flag(CF, false);
     assert_eq!(registers.get_flag(CF), false);
   }
+
+  #[test]
+  fn display() {
+    let mut regs = Registers::new();
+    regs.set_af(0x01B0);
+    regs.set_hl(0x014D);
+    regs.set_sp(0xFFFE);
+    regs.set_pc(0x0100);
+
+    assert_eq!(
+      regs.to_string(),
+      "AF:01B0 BC:0000 DE:0000 HL:014D SP:FFFE PC:0100"
+    );
+  }
 }
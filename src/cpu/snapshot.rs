@@ -0,0 +1,189 @@
+// captured CPU state, to be restored later on
+//
+// the binary encoding is little-endian and starts with a version byte:
+//   version: u8
+//   AF, BC, DE, HL, SP, PC: u16
+//   state flags: u8 (see the STATE_* bits below)
+//   cycles: u32
+//   last instruction cycles: u8
+
+use std::fmt;
+
+pub const VERSION: u8 = 1;
+
+pub const ENCODED_SIZE: usize = 19;
+
+const STATE_IME: u8 = 0b0000_0001;
+const STATE_IME_SCHEDULED: u8 = 0b0000_0010;
+const STATE_HALTED: u8 = 0b0000_0100;
+const STATE_HALT_BUG: u8 = 0b0000_1000;
+const STATE_STOPPED: u8 = 0b0001_0000;
+const STATE_LOCKED: u8 = 0b0010_0000;
+const STATE_DOUBLE_SPEED: u8 = 0b0100_0000;
+const STATE_CGB_MODE: u8 = 0b1000_0000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Snapshot {
+  pub af: u16,
+  pub bc: u16,
+  pub de: u16,
+  pub hl: u16,
+  pub sp: u16,
+  pub pc: u16,
+  pub ime: bool,
+  pub ime_scheduled: bool,
+  pub halted: bool,
+  pub halt_bug: bool,
+  pub stopped: bool,
+  pub locked: bool,
+  pub double_speed: bool,
+  pub cgb_mode: bool,
+  pub cycles: u32,
+  pub last_instr_cycles: u8,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SnapshotError {
+  UnsupportedVersion(u8),
+  Truncated,
+}
+
+impl fmt::Display for SnapshotError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SnapshotError::UnsupportedVersion(version) => {
+        write!(f, "Unsupported CPU snapshot version {}", version)
+      }
+      SnapshotError::Truncated => write!(f, "Truncated CPU snapshot"),
+    }
+  }
+}
+
+impl Snapshot {
+  pub fn encode(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(ENCODED_SIZE);
+    bytes.push(VERSION);
+
+    for reg in &[self.af, self.bc, self.de, self.hl, self.sp, self.pc] {
+      bytes.extend_from_slice(&reg.to_le_bytes());
+    }
+
+    bytes.push(self.state());
+    bytes.extend_from_slice(&self.cycles.to_le_bytes());
+    bytes.push(self.last_instr_cycles);
+
+    bytes
+  }
+
+  pub fn decode(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+    match bytes.first() {
+      Some(&VERSION) => (),
+      Some(&version) => return Err(SnapshotError::UnsupportedVersion(version)),
+      None => return Err(SnapshotError::Truncated),
+    }
+
+    if bytes.len() < ENCODED_SIZE {
+      return Err(SnapshotError::Truncated);
+    }
+
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+    let state = bytes[13];
+
+    Ok(Snapshot {
+      af: u16_at(1),
+      bc: u16_at(3),
+      de: u16_at(5),
+      hl: u16_at(7),
+      sp: u16_at(9),
+      pc: u16_at(11),
+      ime: state & STATE_IME > 0,
+      ime_scheduled: state & STATE_IME_SCHEDULED > 0,
+      halted: state & STATE_HALTED > 0,
+      halt_bug: state & STATE_HALT_BUG > 0,
+      stopped: state & STATE_STOPPED > 0,
+      locked: state & STATE_LOCKED > 0,
+      double_speed: state & STATE_DOUBLE_SPEED > 0,
+      cgb_mode: state & STATE_CGB_MODE > 0,
+      cycles: u32::from_le_bytes([bytes[14], bytes[15], bytes[16], bytes[17]]),
+      last_instr_cycles: bytes[18],
+    })
+  }
+
+  fn state(&self) -> u8 {
+    let flags = [
+      (self.ime, STATE_IME),
+      (self.ime_scheduled, STATE_IME_SCHEDULED),
+      (self.halted, STATE_HALTED),
+      (self.halt_bug, STATE_HALT_BUG),
+      (self.stopped, STATE_STOPPED),
+      (self.locked, STATE_LOCKED),
+      (self.double_speed, STATE_DOUBLE_SPEED),
+      (self.cgb_mode, STATE_CGB_MODE),
+    ];
+
+    flags
+      .iter()
+      .filter(|(set, _)| *set)
+      .fold(0, |state, (_, bit)| state | bit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn snapshot() -> Snapshot {
+    Snapshot {
+      af: 0x01B0,
+      bc: 0x0013,
+      de: 0x00D8,
+      hl: 0x014D,
+      sp: 0xFFFE,
+      pc: 0x0100,
+      ime: true,
+      ime_scheduled: false,
+      halted: true,
+      halt_bug: false,
+      stopped: false,
+      locked: false,
+      double_speed: false,
+      cgb_mode: true,
+      cycles: 0x0102_0304,
+      last_instr_cycles: 12,
+    }
+  }
+
+  #[test]
+  fn encode() {
+    let mut expected = vec![VERSION];
+    expected.extend_from_slice(&[0xB0, 0x01, 0x13, 0x00, 0xD8, 0x00]);
+    expected.extend_from_slice(&[0x4D, 0x01, 0xFE, 0xFF, 0x00, 0x01]);
+    expected.push(0b1000_0101);
+    expected.extend_from_slice(&[0x04, 0x03, 0x02, 0x01]);
+    expected.push(12);
+
+    assert_eq!(snapshot().encode(), expected);
+  }
+
+  #[test]
+  fn decode() {
+    assert_eq!(Snapshot::decode(&snapshot().encode()), Ok(snapshot()));
+  }
+
+  #[test]
+  fn decode_errors() {
+    let mut bytes = snapshot().encode();
+
+    assert_eq!(
+      Snapshot::decode(&bytes[..ENCODED_SIZE - 1]),
+      Err(SnapshotError::Truncated)
+    );
+    assert_eq!(Snapshot::decode(&[]), Err(SnapshotError::Truncated));
+
+    bytes[0] = VERSION + 1;
+    assert_eq!(
+      Snapshot::decode(&bytes),
+      Err(SnapshotError::UnsupportedVersion(VERSION + 1))
+    );
+  }
+}
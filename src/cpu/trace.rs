@@ -0,0 +1,152 @@
+// per-instruction trace log in the Gameboy Doctor format
+// https://github.com/robert/gameboy-doctor
+//
+// with symbols, which are opt-in, each line ends with the label PC is at,
+// such as ` ; Main.loop+3`, which Gameboy Doctor doesn't expect
+
+use super::registers::Registers;
+use crate::debug::symbols::Symbols;
+use crate::mmu::MMU;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub struct Tracer {
+  out: Box<dyn Write>,
+  symbols: Option<Symbols>,
+}
+
+impl Tracer {
+  pub fn new(out: Box<dyn Write>) -> Tracer {
+    Tracer { out, symbols: None }
+  }
+
+  pub fn with_symbols(mut self, symbols: Symbols) -> Tracer {
+    self.symbols = Some(symbols);
+    self
+  }
+
+  // streams the trace into a file, buffering only a few lines at a time
+  pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Tracer> {
+    let file = File::create(path)?;
+
+    Ok(Tracer::new(Box::new(BufWriter::new(file))))
+  }
+
+  // writes the state of the CPU right before executing the instruction at PC
+  pub fn trace<M: MMU>(&mut self, regs: &Registers, mmu: &M) -> io::Result<()> {
+    let pc = regs.pc();
+    // reading memory as the program would could fault or trip a watchpoint
+    let byte = |offset: u16| mmu.peek8(pc.wrapping_add(offset)).unwrap_or(0xFF);
+
+    write!(
+      self.out,
+      "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+      regs.a(),
+      regs.af() as u8,
+      regs.b(),
+      regs.c(),
+      regs.d(),
+      regs.e(),
+      regs.h(),
+      regs.l(),
+      regs.sp(),
+      pc,
+      byte(0),
+      byte(1),
+      byte(2),
+      byte(3),
+    )?;
+
+    match self
+      .symbols
+      .as_ref()
+      .and_then(|symbols| symbols.describe(mmu.rom_bank(pc), pc))
+    {
+      Some(label) => writeln!(self.out, " ; {}", label),
+      None => writeln!(self.out),
+    }
+  }
+
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.out.flush()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mmu::{real_mmu::RealMMU, test_mmu::TestMMU};
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  #[derive(Clone)]
+  struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn trace(mut tracer: Tracer, buffer: &SharedBuffer) -> String {
+    let mut regs = Registers::new();
+    let mut mmu = TestMMU::new();
+
+    regs.set_af(0x01B0);
+    regs.set_bc(0x0013);
+    regs.set_de(0x00D8);
+    regs.set_hl(0x014D);
+    regs.set_sp(0xFFFE);
+    regs.set_pc(0x0100);
+    mmu.write8(0x0101u16, 0xC3);
+    mmu.write8(0x0102u16, 0x13);
+    mmu.write8(0x0103u16, 0x02);
+
+    tracer.trace(&regs, &mmu).unwrap();
+
+    String::from_utf8(buffer.0.borrow().clone()).unwrap()
+  }
+
+  #[test]
+  fn trace_line() {
+    let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+    let tracer = Tracer::new(Box::new(buffer.clone()));
+
+    assert_eq!(
+      trace(tracer, &buffer),
+      "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02\n"
+    );
+  }
+
+  #[test]
+  fn trace_unmapped() {
+    let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+    let mut tracer = Tracer::new(Box::new(buffer.clone()));
+    let mut regs = Registers::new();
+    let mut mmu = RealMMU::new(false, vec![0; 0x8000]);
+    regs.set_pc(0xDFFE);
+
+    tracer.trace(&regs, &mmu).unwrap();
+    assert!(String::from_utf8(buffer.0.borrow().clone())
+      .unwrap()
+      .ends_with("PC:DFFE PCMEM:00,00,FF,FF\n"));
+    assert_eq!(mmu.take_fault(), None);
+  }
+
+  #[test]
+  fn trace_line_symbols() {
+    let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+    let symbols = Symbols::parse("00:00FD Boot.loop").unwrap();
+    let tracer = Tracer::new(Box::new(buffer.clone())).with_symbols(symbols);
+
+    assert_eq!(
+      trace(tracer, &buffer),
+      "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02 ; Boot.loop+3\n"
+    );
+  }
+}
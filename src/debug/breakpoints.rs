@@ -0,0 +1,142 @@
+// breakpoints checked before every instruction executed
+
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Breakpoint {
+  // PC, whatever bank is mapped in
+  Address(u16),
+  // PC within the given ROM bank
+  BankAddress(u8, u16),
+  // any instruction starting with this byte, such as LD B,B ($40)
+  Opcode(u8),
+}
+
+impl fmt::Display for Breakpoint {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Breakpoint::Address(address) => write!(f, "${:04X}", address),
+      Breakpoint::BankAddress(bank, address) => write!(f, "${:02X}:{:04X}", bank, address),
+      Breakpoint::Opcode(opcode) => write!(f, "opcode ${:02X}", opcode),
+    }
+  }
+}
+
+pub struct Breakpoints {
+  set: HashSet<Breakpoint>,
+  opcodes: usize,
+}
+
+impl Breakpoints {
+  pub fn new() -> Breakpoints {
+    Breakpoints {
+      set: HashSet::new(),
+      opcodes: 0,
+    }
+  }
+
+  #[allow(dead_code)]
+  pub fn add(&mut self, breakpoint: Breakpoint) {
+    if self.set.insert(breakpoint) {
+      if let Breakpoint::Opcode(_) = breakpoint {
+        self.opcodes += 1;
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  pub fn remove(&mut self, breakpoint: Breakpoint) {
+    if self.set.remove(&breakpoint) {
+      if let Breakpoint::Opcode(_) = breakpoint {
+        self.opcodes -= 1;
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  pub fn clear(&mut self) {
+    self.set.clear();
+    self.opcodes = 0;
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.set.is_empty()
+  }
+
+  // whether the opcode is needed to check breakpoints at all,
+  // so that it's only peeked at when it is
+  pub fn has_opcodes(&self) -> bool {
+    self.opcodes > 0
+  }
+
+  // the breakpoint hit by the instruction at PC, if any. `bank` is the
+  // ROM bank PC lies in, if it's in ROM
+  pub fn check(&self, pc: u16, bank: Option<u8>, opcode: Option<u8>) -> Option<Breakpoint> {
+    let candidates = [
+      Some(Breakpoint::Address(pc)),
+      bank.map(|bank| Breakpoint::BankAddress(bank, pc)),
+      opcode.map(Breakpoint::Opcode),
+    ];
+
+    candidates
+      .iter()
+      .flatten()
+      .find(|breakpoint| self.set.contains(breakpoint))
+      .cloned()
+  }
+}
+
+impl Default for Breakpoints {
+  fn default() -> Breakpoints {
+    Breakpoints::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn check_address() {
+    let mut breakpoints = Breakpoints::new();
+    breakpoints.add(Breakpoint::Address(0x0150));
+
+    assert_eq!(
+      breakpoints.check(0x0150, Some(0), None),
+      Some(Breakpoint::Address(0x0150))
+    );
+    assert_eq!(breakpoints.check(0x0151, Some(0), None), None);
+  }
+
+  #[test]
+  fn check_bank_address() {
+    let mut breakpoints = Breakpoints::new();
+    breakpoints.add(Breakpoint::BankAddress(1, 0x4000));
+
+    assert_eq!(
+      breakpoints.check(0x4000, Some(1), None),
+      Some(Breakpoint::BankAddress(1, 0x4000))
+    );
+    assert_eq!(breakpoints.check(0x4000, Some(2), None), None);
+    assert_eq!(breakpoints.check(0x4000, None, None), None);
+  }
+
+  #[test]
+  fn check_opcode() {
+    let mut breakpoints = Breakpoints::new();
+    assert_eq!(breakpoints.has_opcodes(), false);
+
+    breakpoints.add(Breakpoint::Opcode(0x40));
+    assert_eq!(breakpoints.has_opcodes(), true);
+    assert_eq!(
+      breakpoints.check(0xC000, None, Some(0x40)),
+      Some(Breakpoint::Opcode(0x40))
+    );
+    assert_eq!(breakpoints.check(0xC000, None, Some(0x41)), None);
+
+    breakpoints.remove(Breakpoint::Opcode(0x40));
+    assert_eq!(breakpoints.has_opcodes(), false);
+    assert_eq!(breakpoints.is_empty(), true);
+  }
+}
@@ -0,0 +1,72 @@
+// Code/Data map of a cartridge: one byte of flags per ROM byte,
+// laid out like FCEUX .cdl files, with the bit meanings BizHawk uses for
+// Game Boy cartridges. byte N of the map is ROM byte N, which is mapped at
+// N % $4000 in bank N / $4000
+
+pub const EXEC_FIRST: u8 = 0b0000_0001; // first byte of an executed instruction
+pub const EXEC_OPERAND: u8 = 0b0000_0010; // operand of an executed instruction
+pub const DATA: u8 = 0b0000_0100; // read as data
+
+pub fn is_code(flags: u8) -> bool {
+  flags & (EXEC_FIRST | EXEC_OPERAND) > 0
+}
+
+// bytes only ever read as data, and never executed
+pub fn is_data(flags: u8) -> bool {
+  flags & DATA > 0 && !is_code(flags)
+}
+
+const BANK_SIZE: usize = 0x4000;
+
+// builds the map of a cartridge as it runs
+pub struct Logger {
+  flags: Vec<u8>,
+}
+
+impl Logger {
+  pub fn new(rom_size: usize) -> Logger {
+    Logger {
+      flags: vec![0; rom_size],
+    }
+  }
+
+  // marks the ROM byte at the address of the given bank
+  pub fn log(&mut self, bank: u8, address: u16, flags: u8) {
+    let offset = bank as usize * BANK_SIZE + address as usize % BANK_SIZE;
+
+    if let Some(byte) = self.flags.get_mut(offset) {
+      *byte |= flags;
+    }
+  }
+
+  pub fn flags(&self) -> &[u8] {
+    &self.flags
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn log() {
+    let mut logger = Logger::new(BANK_SIZE * 2);
+    logger.log(0, 0x0150, EXEC_FIRST);
+    logger.log(0, 0x0150, DATA);
+    logger.log(1, 0x4001, EXEC_OPERAND);
+    logger.log(2, 0x4000, DATA);
+
+    assert_eq!(logger.flags()[0x0150], EXEC_FIRST | DATA);
+    assert_eq!(logger.flags()[0x4001], EXEC_OPERAND);
+    assert_eq!(logger.flags().len(), BANK_SIZE * 2);
+  }
+
+  #[test]
+  fn classify() {
+    assert_eq!(is_code(EXEC_FIRST), true);
+    assert_eq!(is_code(EXEC_OPERAND | DATA), true);
+    assert_eq!(is_data(DATA), true);
+    assert_eq!(is_data(DATA | EXEC_OPERAND), false);
+    assert_eq!(is_data(0), false);
+  }
+}
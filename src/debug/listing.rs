@@ -0,0 +1,349 @@
+// writes a whole cartridge as a disassembly listing, grouped by ROM bank
+
+use super::cdl;
+use super::symbols::Symbols;
+use crate::cpu::disasm;
+use std::io::{self, Write};
+
+pub const BANK_SIZE: usize = 0x4000;
+
+// restart and interrupt vectors, and the entry point
+const LABELS: [(u16, &str); 14] = [
+  (0x0000, "RST_00"),
+  (0x0008, "RST_08"),
+  (0x0010, "RST_10"),
+  (0x0018, "RST_18"),
+  (0x0020, "RST_20"),
+  (0x0028, "RST_28"),
+  (0x0030, "RST_30"),
+  (0x0038, "RST_38"),
+  (0x0040, "VBlankInterrupt"),
+  (0x0048, "LCDStatInterrupt"),
+  (0x0050, "TimerInterrupt"),
+  (0x0058, "SerialInterrupt"),
+  (0x0060, "JoypadInterrupt"),
+  (0x0100, "EntryPoint"),
+];
+
+// cartridge header fields following the entry point
+// https://gbdev.io/pandocs/The_Cartridge_Header.html
+const HEADER: [(u16, u16, &str); 14] = [
+  (0x0104, 0x0133, "Nintendo logo"),
+  (0x0134, 0x013E, "Title"),
+  (0x013F, 0x0142, "Manufacturer code"),
+  (0x0143, 0x0143, "CGB flag"),
+  (0x0144, 0x0145, "New licensee code"),
+  (0x0146, 0x0146, "SGB flag"),
+  (0x0147, 0x0147, "Cartridge type"),
+  (0x0148, 0x0148, "ROM size"),
+  (0x0149, 0x0149, "RAM size"),
+  (0x014A, 0x014A, "Destination code"),
+  (0x014B, 0x014B, "Old licensee code"),
+  (0x014C, 0x014C, "Mask ROM version number"),
+  (0x014D, 0x014D, "Header checksum"),
+  (0x014E, 0x014F, "Global checksum"),
+];
+
+// maximum number of bytes in a single `db` line
+const DB_WIDTH: usize = 8;
+
+// labels from the symbols take the place of the built-in ones, and name
+// the targets of jumps and calls
+pub fn write_listing<W: Write>(
+  rom: &[u8],
+  cdl: Option<&[u8]>,
+  symbols: Option<&Symbols>,
+  out: &mut W,
+) -> io::Result<()> {
+  for (index, data) in rom.chunks(BANK_SIZE).enumerate() {
+    let start = index * BANK_SIZE;
+    let flags = cdl.map(|cdl| cdl.get(start..).unwrap_or(&[]));
+
+    let bank = Bank {
+      index,
+      base: if index == 0 { 0 } else { BANK_SIZE as u16 },
+      data,
+      flags,
+      symbols,
+    };
+
+    writeln!(out, "; ROM bank ${:02X}", index)?;
+    bank.write(out)?;
+    writeln!(out)?;
+  }
+
+  Ok(())
+}
+
+struct Bank<'a> {
+  index: usize,
+  base: u16,
+  data: &'a [u8],
+  flags: Option<&'a [u8]>,
+  symbols: Option<&'a Symbols>,
+}
+
+impl<'a> Bank<'a> {
+  fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    let mut offset = 0;
+
+    while offset < self.data.len() {
+      let address = self.address(offset);
+
+      for label in self.labels(offset) {
+        writeln!(out, "{}:", label)?;
+      }
+
+      if let Some((_, end, name)) = self.header_field(offset) {
+        let len = (end - address + 1) as usize;
+        self.write_db(out, offset, len, Some(name))?;
+        offset += len;
+        continue;
+      }
+
+      if self.is_data(offset) {
+        let len = (1..DB_WIDTH)
+          .find(|&i| {
+            offset + i >= self.data.len() || !self.is_data(offset + i) || self.boundary(offset + i)
+          })
+          .unwrap_or(DB_WIDTH);
+        self.write_db(out, offset, len, None)?;
+        offset += len;
+        continue;
+      }
+
+      let instruction = disasm::disassemble_slice(&self.data[offset..], address);
+      let size = instruction.size() as usize;
+
+      // an instruction can't run past the end of the bank or into
+      // something that isn't code, so its first byte is emitted as data
+      if offset + size > self.data.len()
+        || (1..size).any(|i| self.boundary(offset + i) || self.is_data(offset + i))
+      {
+        self.write_db(out, offset, 1, None)?;
+        offset += 1;
+        continue;
+      }
+
+      let bytes: Vec<String> = instruction
+        .bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+
+      let text = match instruction
+        .target
+        .and_then(|target| self.target_label(target))
+      {
+        Some(label) => instruction
+          .text
+          .replace(&format!("${:04X}", instruction.target.unwrap()), label),
+        None => instruction.text,
+      };
+
+      writeln!(
+        out,
+        "{:02X}:{:04X}  {:<9} {}",
+        self.index,
+        address,
+        bytes.join(" "),
+        text
+      )?;
+
+      offset += size;
+    }
+
+    Ok(())
+  }
+
+  fn write_db<W: Write>(
+    &self,
+    out: &mut W,
+    offset: usize,
+    len: usize,
+    comment: Option<&str>,
+  ) -> io::Result<()> {
+    let end = usize::min(offset + len, self.data.len());
+
+    for (i, chunk) in self.data[offset..end].chunks(DB_WIDTH).enumerate() {
+      let address = self.address(offset + i * DB_WIDTH);
+      let bytes: Vec<String> = chunk.iter().map(|byte| format!("${:02X}", byte)).collect();
+
+      write!(
+        out,
+        "{:02X}:{:04X}            db {}",
+        self.index,
+        address,
+        bytes.join(", ")
+      )?;
+
+      match comment {
+        Some(comment) if i == 0 => writeln!(out, " ; {}", comment)?,
+        _ => writeln!(out)?,
+      }
+    }
+
+    Ok(())
+  }
+
+  fn address(&self, offset: usize) -> u16 {
+    self.base + offset as u16
+  }
+
+  fn labels(&self, offset: usize) -> Vec<&'a str> {
+    let address = self.address(offset);
+
+    if let Some(symbols) = self.symbols {
+      let labels = symbols.labels(self.index as u8, address);
+
+      if !labels.is_empty() {
+        return labels.iter().map(|label| label.as_str()).collect();
+      }
+    }
+
+    if self.index > 0 {
+      return Vec::new();
+    }
+
+    LABELS
+      .iter()
+      .filter(|(label_address, _)| *label_address == address)
+      .map(|(_, label)| *label)
+      .collect()
+  }
+
+  // the label at a jump or call target. targets in the switchable bank
+  // are assumed to be in this one, which is unknown from bank 0
+  fn target_label(&self, target: u16) -> Option<&'a str> {
+    let bank = match target {
+      0x0000..=0x3FFF => 0,
+      0x4000..=0x7FFF if self.index > 0 => self.index as u8,
+      0x4000..=0x7FFF => return None,
+      _ => 0,
+    };
+
+    self
+      .symbols?
+      .labels(bank, target)
+      .first()
+      .map(|label| label.as_str())
+  }
+
+  fn header_field(&self, offset: usize) -> Option<(u16, u16, &'static str)> {
+    if self.index > 0 {
+      return None;
+    }
+
+    let address = self.address(offset);
+
+    HEADER
+      .iter()
+      .find(|(start, end, _)| *start <= address && address <= *end)
+      .cloned()
+  }
+
+  // whether a new line must start at this offset
+  fn boundary(&self, offset: usize) -> bool {
+    !self.labels(offset).is_empty() || self.header_field(offset).is_some()
+  }
+
+  fn is_data(&self, offset: usize) -> bool {
+    match self.flags.and_then(|flags| flags.get(offset)) {
+      Some(&flags) => cdl::is_data(flags),
+      None => false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn listing(rom: &[u8], cdl: Option<&[u8]>) -> String {
+    listing_with_symbols(rom, cdl, None)
+  }
+
+  fn listing_with_symbols(rom: &[u8], cdl: Option<&[u8]>, symbols: Option<&Symbols>) -> String {
+    let mut out = Vec::new();
+    write_listing(rom, cdl, symbols, &mut out).unwrap();
+
+    String::from_utf8(out).unwrap()
+  }
+
+  fn test_rom() -> Vec<u8> {
+    let mut rom = vec![0; BANK_SIZE * 2];
+    rom[0x0000..0x0003].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    rom[0x003F] = 0x3E;
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x0134..0x0138].copy_from_slice(b"TEST");
+    rom[0x0150..0x0153].copy_from_slice(&[0xCD, 0x00, 0x40]);
+    rom[BANK_SIZE] = 0xC9;
+
+    rom
+  }
+
+  #[test]
+  fn listing_banks() {
+    let out = listing(&test_rom(), None);
+
+    assert!(out.contains("; ROM bank $00\nRST_00:\n00:0000  C3 50 01  jp $0150\n"));
+    assert!(out.contains("00:0150  CD 00 40  call $4000\n"));
+    assert!(out.contains("; ROM bank $01\n01:4000  C9        ret\n"));
+  }
+
+  #[test]
+  fn listing_vectors() {
+    let out = listing(&test_rom(), None);
+
+    assert!(out.contains("RST_38:\n00:0038  00        nop\n"));
+    assert!(out.contains("VBlankInterrupt:\n00:0040"));
+    assert!(out.contains("JoypadInterrupt:\n00:0060"));
+    assert!(out.contains("EntryPoint:\n00:0100  00        nop\n00:0101  C3 50 01  jp $0150\n"));
+  }
+
+  #[test]
+  fn listing_instruction_across_label() {
+    let out = listing(&test_rom(), None);
+
+    assert!(out.contains("00:003F            db $3E\nVBlankInterrupt:\n"));
+  }
+
+  #[test]
+  fn listing_header() {
+    let out = listing(&test_rom(), None);
+
+    assert!(out
+      .contains("00:0104            db $00, $00, $00, $00, $00, $00, $00, $00 ; Nintendo logo\n"));
+    assert!(out.contains("00:0134            db $54, $45, $53, $54, $00, $00, $00, $00 ; Title\n"));
+    assert!(out.contains("00:0147            db $00 ; Cartridge type\n"));
+    assert!(out.contains("00:014E            db $00, $00 ; Global checksum\n"));
+  }
+
+  #[test]
+  fn listing_code_data_map() {
+    let rom = test_rom();
+    let mut flags = vec![0; rom.len()];
+    flags[0x0150] = cdl::DATA;
+    flags[0x0151] = cdl::DATA;
+    flags[0x0152] = cdl::DATA | cdl::EXEC_FIRST;
+
+    let out = listing(&rom, Some(&flags));
+
+    assert!(out.contains("00:0150            db $CD, $00\n00:0152  40        ld b, b\n"));
+  }
+
+  #[test]
+  fn listing_symbols() {
+    let symbols =
+      Symbols::parse("00:0100 Start\n00:0150 Main\n00:0150 Main.init\n01:4000 Bank1Routine\n")
+        .unwrap();
+
+    let out = listing_with_symbols(&test_rom(), None, Some(&symbols));
+
+    assert!(out.contains("Start:\n00:0100  00        nop\n00:0101  C3 50 01  jp Main\n"));
+    assert!(!out.contains("EntryPoint:"));
+    assert!(out.contains("RST_00:\n00:0000  C3 50 01  jp Main\n"));
+    assert!(out.contains("Main:\nMain.init:\n00:0150  CD 00 40  call $4000\n"));
+    assert!(out.contains("Bank1Routine:\n01:4000  C9        ret\n"));
+  }
+}
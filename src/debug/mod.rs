@@ -0,0 +1,5 @@
+pub mod breakpoints;
+pub mod cdl;
+pub mod listing;
+pub mod symbols;
+pub mod watchpoints;
@@ -0,0 +1,245 @@
+// labels read from an RGBDS .sym file, one `bank:address label` per line
+// https://rgbds.gbdev.io/docs/rgblink.1#Symbol_files
+
+use super::breakpoints::Breakpoint;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+type Location = (u8, u16);
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+  by_name: HashMap<String, Location>,
+  // every label at a location, in the order they appear in the file
+  by_location: BTreeMap<Location, Vec<String>>,
+}
+
+impl Symbols {
+  pub fn new() -> Symbols {
+    Symbols {
+      by_name: HashMap::new(),
+      by_location: BTreeMap::new(),
+    }
+  }
+
+  pub fn parse(text: &str) -> Result<Symbols, String> {
+    let mut symbols = Symbols::new();
+
+    for (index, line) in text.lines().enumerate() {
+      let line = line.split(';').next().unwrap().trim();
+
+      if line.is_empty() {
+        continue;
+      }
+
+      let (bank, address, name) = parse_line(line)
+        .ok_or_else(|| format!("Invalid symbol on line {}: {}", index + 1, line))?;
+
+      symbols.add(bank, address, name);
+    }
+
+    Ok(symbols)
+  }
+
+  pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Symbols> {
+    let text = fs::read_to_string(path)?;
+
+    Symbols::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+  }
+
+  // the symbols of the .sym file next to the cartridge, as RGBDS names it,
+  // if there is one
+  pub fn load_for_cartridge<P: AsRef<Path>>(cartridge_path: P) -> io::Result<Option<Symbols>> {
+    let path = cartridge_path.as_ref().with_extension("sym");
+
+    if !path.is_file() {
+      return Ok(None);
+    }
+
+    Symbols::load(path).map(Some)
+  }
+
+  // like `load_for_cartridge`, but symbols being optional, a .sym file that
+  // can't be loaded is only warned about
+  pub fn try_load_for_cartridge<P: AsRef<Path>>(cartridge_path: P) -> Option<Symbols> {
+    match Symbols::load_for_cartridge(cartridge_path) {
+      Ok(symbols) => symbols,
+      Err(err) => {
+        eprintln!("Ignoring symbol file: {}", err);
+        None
+      }
+    }
+  }
+
+  pub fn add(&mut self, bank: u8, address: u16, name: &str) {
+    self.by_name.insert(name.to_string(), (bank, address));
+    self
+      .by_location
+      .entry((bank, address))
+      .or_default()
+      .push(name.to_string());
+  }
+
+  pub fn location(&self, name: &str) -> Option<(u8, u16)> {
+    self.by_name.get(name).cloned()
+  }
+
+  // a breakpoint on the label, tied to its bank when it's in ROM
+  #[allow(dead_code)]
+  pub fn breakpoint(&self, name: &str) -> Option<Breakpoint> {
+    self.location(name).map(|(bank, address)| {
+      if address < 0x8000 {
+        Breakpoint::BankAddress(bank, address)
+      } else {
+        Breakpoint::Address(address)
+      }
+    })
+  }
+
+  // the labels at exactly this location
+  pub fn labels(&self, bank: u8, address: u16) -> &[String] {
+    match self.by_location.get(&(bank, address)) {
+      Some(labels) => labels,
+      None => &[],
+    }
+  }
+
+  // the closest label at or before the address, within the same bank and
+  // memory region, such as `Main.loop+3`. `bank` is the ROM bank the
+  // address lies in, and anything outside of ROM is looked up in bank 0
+  pub fn describe(&self, bank: Option<u8>, address: u16) -> Option<String> {
+    let bank = bank.unwrap_or(0);
+    let start = region_start(address);
+
+    let (&(_, label_address), labels) = self
+      .by_location
+      .range((bank, start)..=(bank, address))
+      .next_back()?;
+
+    match address - label_address {
+      0 => Some(labels[0].clone()),
+      offset => Some(format!("{}+{}", labels[0], offset)),
+    }
+  }
+}
+
+fn parse_line(line: &str) -> Option<(u8, u16, &str)> {
+  let mut parts = line.split_whitespace();
+  let location = parts.next()?;
+  let name = parts.next()?;
+
+  if parts.next().is_some() {
+    return None;
+  }
+
+  let mut location = location.splitn(2, ':');
+  let bank = u8::from_str_radix(location.next()?, 16).ok()?;
+  let address = u16::from_str_radix(location.next()?, 16).ok()?;
+
+  Some((bank, address, name))
+}
+
+// start of the memory region the address is in, so that labels
+// in one region never describe addresses in the next
+fn region_start(address: u16) -> u16 {
+  match address {
+    0x0000..=0x3FFF => 0x0000,
+    0x4000..=0x7FFF => 0x4000,
+    0x8000..=0x9FFF => 0x8000,
+    0xA000..=0xBFFF => 0xA000,
+    0xC000..=0xDFFF => 0xC000,
+    0xE000..=0xFDFF => 0xE000,
+    0xFE00..=0xFF7F => 0xFE00,
+    _ => 0xFF80,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SYM: &str = "; File generated by rgblink\n\
+                     00:0150 Main\n\
+                     00:0150 Main.init\n\
+                     00:0158 Main.loop\n\
+                     01:4000 Bank1Routine\n\
+                     00:c000 wCounter\n\
+                     00:ff80 hFrame ; comment\n";
+
+  #[test]
+  fn parse() {
+    let symbols = Symbols::parse(SYM).unwrap();
+
+    assert_eq!(symbols.location("Main.loop"), Some((0, 0x0158)));
+    assert_eq!(symbols.location("hFrame"), Some((0, 0xFF80)));
+    assert_eq!(symbols.location("Missing"), None);
+    assert_eq!(symbols.labels(0, 0x0150), ["Main", "Main.init"]);
+    assert!(symbols.labels(0, 0x0151).is_empty());
+  }
+
+  #[test]
+  fn parse_errors() {
+    assert_eq!(
+      Symbols::parse("00:0150 Main\n00:zz Broken\n").err(),
+      Some("Invalid symbol on line 2: 00:zz Broken".to_string())
+    );
+    assert!(Symbols::parse("0150 Main").is_err());
+    assert!(Symbols::parse("00:0150 Main Extra").is_err());
+  }
+
+  #[test]
+  fn try_load_for_cartridge() {
+    let dir = std::env::temp_dir().join(format!("rgba-symbols-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let cartridge = dir.join("game.gb");
+
+    assert!(Symbols::try_load_for_cartridge(&cartridge).is_none());
+
+    fs::write(dir.join("game.sym"), "00:zz Broken\n").unwrap();
+    assert!(Symbols::try_load_for_cartridge(&cartridge).is_none());
+
+    fs::write(dir.join("game.sym"), SYM).unwrap();
+    assert!(Symbols::try_load_for_cartridge(&cartridge).is_some());
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn describe() {
+    let symbols = Symbols::parse(SYM).unwrap();
+
+    assert_eq!(symbols.describe(Some(0), 0x0150), Some("Main".to_string()));
+    assert_eq!(
+      symbols.describe(Some(0), 0x015B),
+      Some("Main.loop+3".to_string())
+    );
+    assert_eq!(
+      symbols.describe(Some(1), 0x4002),
+      Some("Bank1Routine+2".to_string())
+    );
+    assert_eq!(symbols.describe(Some(2), 0x4002), None);
+    assert_eq!(symbols.describe(Some(0), 0x0100), None);
+    assert_eq!(
+      symbols.describe(None, 0xC001),
+      Some("wCounter+1".to_string())
+    );
+    assert_eq!(symbols.describe(None, 0xFE00), None);
+  }
+
+  #[test]
+  fn breakpoint() {
+    let symbols = Symbols::parse(SYM).unwrap();
+
+    assert_eq!(
+      symbols.breakpoint("Bank1Routine"),
+      Some(Breakpoint::BankAddress(1, 0x4000))
+    );
+    assert_eq!(
+      symbols.breakpoint("hFrame"),
+      Some(Breakpoint::Address(0xFF80))
+    );
+    assert_eq!(symbols.breakpoint("Missing"), None);
+  }
+}
@@ -0,0 +1,350 @@
+// memory watchpoints, checked by wrapping the MMU the CPU executes against
+
+use crate::mmu::{Fault, MMU};
+use std::cell::Cell;
+use std::fmt;
+
+#[allow(dead_code)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Trigger {
+  Read,
+  Write,
+  // a write of this exact value, such as a counter becoming 0
+  WriteValue(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Access {
+  Read,
+  Write(u8),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Watchpoint {
+  pub start: u16,
+  pub end: u16,
+  pub trigger: Trigger,
+}
+
+impl Watchpoint {
+  #[allow(dead_code)]
+  pub fn new(start: u16, end: u16, trigger: Trigger) -> Watchpoint {
+    Watchpoint {
+      start,
+      end,
+      trigger,
+    }
+  }
+
+  fn fires(&self, address: u16, access: Access) -> bool {
+    if address < self.start || address > self.end {
+      return false;
+    }
+
+    match (self.trigger, access) {
+      (Trigger::Read, Access::Read) => true,
+      (Trigger::Write, Access::Write(_)) => true,
+      (Trigger::WriteValue(value), Access::Write(new)) => value == new,
+      _ => false,
+    }
+  }
+}
+
+// a watchpoint firing on an access by the instruction at `pc`.
+// for reads, `old` and `new` are both the value read
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Hit {
+  pub watchpoint: Watchpoint,
+  pub pc: u16,
+  pub address: u16,
+  pub old: u8,
+  pub new: u8,
+}
+
+impl fmt::Display for Hit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.watchpoint.trigger {
+      Trigger::Read => write!(
+        f,
+        "${:04X} read by ${:04X}: ${:02X}",
+        self.address, self.pc, self.new
+      ),
+      _ => write!(
+        f,
+        "${:04X} written by ${:04X}: ${:02X} -> ${:02X}",
+        self.address, self.pc, self.old, self.new
+      ),
+    }
+  }
+}
+
+pub struct Watchpoints {
+  list: Vec<Watchpoint>,
+}
+
+impl Watchpoints {
+  pub fn new() -> Watchpoints {
+    Watchpoints { list: Vec::new() }
+  }
+
+  #[allow(dead_code)]
+  pub fn add(&mut self, watchpoint: Watchpoint) {
+    self.list.push(watchpoint);
+  }
+
+  #[allow(dead_code)]
+  pub fn remove(&mut self, watchpoint: Watchpoint) {
+    self.list.retain(|w| *w != watchpoint);
+  }
+
+  #[allow(dead_code)]
+  pub fn clear(&mut self) {
+    self.list.clear();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.list.is_empty()
+  }
+
+  fn find(&self, address: u16, access: Access) -> Option<Watchpoint> {
+    self
+      .list
+      .iter()
+      .find(|watchpoint| watchpoint.fires(address, access))
+      .cloned()
+  }
+
+  fn watches_writes(&self, address: u16) -> bool {
+    self.list.iter().any(|watchpoint| {
+      watchpoint.trigger != Trigger::Read
+        && watchpoint.start <= address
+        && address <= watchpoint.end
+    })
+  }
+}
+
+impl Default for Watchpoints {
+  fn default() -> Watchpoints {
+    Watchpoints::new()
+  }
+}
+
+// forwards every access to the inner MMU, recording the first watchpoint hit
+pub struct Watched<'a, M: MMU> {
+  inner: M,
+  watchpoints: &'a Watchpoints,
+  pc: u16,
+  hit: Cell<Option<Hit>>,
+}
+
+impl<'a, M: MMU> Watched<'a, M> {
+  // `pc` is the address of the instruction about to access memory
+  pub fn new(inner: M, watchpoints: &'a Watchpoints, pc: u16) -> Watched<'a, M> {
+    Watched {
+      inner,
+      watchpoints,
+      pc,
+      hit: Cell::new(None),
+    }
+  }
+
+  pub fn take_hit(&mut self) -> Option<Hit> {
+    self.hit.take()
+  }
+
+  fn check(&self, address: usize, access: Access, old: u8, new: u8) {
+    if self.hit.get().is_some() {
+      return;
+    }
+
+    let address = address as u16;
+
+    if let Some(watchpoint) = self.watchpoints.find(address, access) {
+      self.hit.set(Some(Hit {
+        watchpoint,
+        pc: self.pc,
+        address,
+        old,
+        new,
+      }));
+    }
+  }
+
+  // the current value, only when a write to the address could fire,
+  // so that unwatched writes don't read anything. peeking leaves the fault
+  // of an unmapped write as the only one
+  fn watched_value(&self, address: usize) -> u8 {
+    if self.watchpoints.watches_writes(address as u16) {
+      self.inner.peek8(address as u16).unwrap_or(0xFF)
+    } else {
+      0
+    }
+  }
+}
+
+impl<'a, M: MMU> MMU for Watched<'a, M> {
+  fn read8<I>(&self, index: I) -> u8
+  where
+    I: Into<usize>,
+  {
+    let address: usize = index.into();
+    let value = self.inner.read8(address);
+    self.check(address, Access::Read, value, value);
+
+    value
+  }
+
+  fn read16<I>(&self, index: I) -> u16
+  where
+    I: Into<usize>,
+  {
+    let address: usize = index.into();
+
+    ((self.read8(address + 1) as u16) << 8) | (self.read8(address) as u16)
+  }
+
+  fn write8<I>(&mut self, index: I, value: u8)
+  where
+    I: Into<usize>,
+  {
+    let address: usize = index.into();
+    let old = self.watched_value(address);
+    self.inner.write8(address, value);
+    self.check(address, Access::Write(value), old, value);
+  }
+
+  fn write16<I>(&mut self, index: I, value: u16)
+  where
+    I: Into<usize>,
+  {
+    let address: usize = index.into();
+
+    self.write8(address, (value & 0x00FF) as u8);
+    self.write8(address + 1, ((value & 0xFF00) >> 8) as u8);
+  }
+
+  fn set_flag<I, U>(&mut self, address: I, mask: U)
+  where
+    I: Into<usize>,
+    U: Into<u8>,
+  {
+    let address: usize = address.into();
+    let old = self.watched_value(address);
+    self.inner.set_flag(address, mask);
+    let new = self.watched_value(address);
+    self.check(address, Access::Write(new), old, new);
+  }
+
+  fn unset_flag<I, U>(&mut self, address: I, mask: U)
+  where
+    I: Into<usize>,
+    U: Into<u8>,
+  {
+    let address: usize = address.into();
+    let old = self.watched_value(address);
+    self.inner.unset_flag(address, mask);
+    let new = self.watched_value(address);
+    self.check(address, Access::Write(new), old, new);
+  }
+
+  fn get_flag<I, U>(&self, address: I, mask: U) -> bool
+  where
+    I: Into<usize>,
+    U: Into<u8>,
+  {
+    let address: usize = address.into();
+    let value = self.inner.read8(address);
+    self.check(address, Access::Read, value, value);
+
+    self.inner.get_flag(address, mask)
+  }
+
+  fn tick(&mut self, cycles: u8) {
+    self.inner.tick(cycles);
+  }
+
+  fn rom_bank(&self, address: u16) -> Option<u8> {
+    self.inner.rom_bank(address)
+  }
+
+  fn peek8(&self, address: u16) -> Option<u8> {
+    self.inner.peek8(address)
+  }
+
+  fn poke8(&mut self, address: u16, value: u8) {
+    self.inner.poke8(address, value)
+  }
+
+  fn take_fault(&mut self) -> Option<Fault> {
+    self.inner.take_fault()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mmu::{real_mmu::RealMMU, test_mmu::TestMMU};
+
+  #[test]
+  fn write() {
+    let mut watchpoints = Watchpoints::new();
+    watchpoints.add(Watchpoint::new(0xC0A0, 0xC0AF, Trigger::Write));
+
+    let mut inner = TestMMU::new();
+    inner.write8(0xC0A2u16, 5);
+
+    let mut mmu = Watched::new(inner, &watchpoints, 0x0150);
+    mmu.write8(0xC0B0u16, 1);
+    assert_eq!(mmu.take_hit(), None);
+
+    mmu.write8(0xC0A2u16, 0);
+    mmu.write8(0xC0A3u16, 0);
+    let hit = mmu.take_hit().unwrap();
+    assert_eq!(hit.address, 0xC0A2);
+    assert_eq!((hit.pc, hit.old, hit.new), (0x0150, 5, 0));
+    assert_eq!(hit.to_string(), "$C0A2 written by $0150: $05 -> $00");
+    assert_eq!(mmu.take_hit(), None);
+  }
+
+  #[test]
+  fn write_value() {
+    let mut watchpoints = Watchpoints::new();
+    watchpoints.add(Watchpoint::new(0xC0A2, 0xC0A2, Trigger::WriteValue(0)));
+
+    let mut mmu = Watched::new(TestMMU::new(), &watchpoints, 0x0150);
+    mmu.write8(0xC0A2u16, 1);
+    assert_eq!(mmu.take_hit(), None);
+
+    mmu.write8(0xC0A2u16, 0);
+    assert_eq!(mmu.take_hit().map(|hit| hit.old), Some(1));
+  }
+
+  #[test]
+  fn read() {
+    let mut watchpoints = Watchpoints::new();
+    watchpoints.add(Watchpoint::new(0xFF44, 0xFF44, Trigger::Read));
+
+    let mut mmu = Watched::new(TestMMU::new(), &watchpoints, 0x0200);
+    mmu.write8(0xFF44u16, 0x90);
+    assert_eq!(mmu.take_hit(), None);
+
+    assert_eq!(mmu.read8(0xFF44u16), 0x90);
+    assert_eq!(
+      mmu.take_hit().map(|hit| hit.to_string()),
+      Some("$FF44 read by $0200: $90".to_string())
+    );
+  }
+
+  #[test]
+  fn write_unmapped() {
+    let mut watchpoints = Watchpoints::new();
+    watchpoints.add(Watchpoint::new(0xFEA0, 0xFEFF, Trigger::Write));
+
+    let inner = RealMMU::new(false, vec![0; 0x8000]);
+    let mut mmu = Watched::new(inner, &watchpoints, 0x0150);
+    mmu.write8(0xFEA0u16, 1);
+
+    assert_eq!(mmu.take_fault(), Some(Fault::Write(0xFEA0)));
+    assert_eq!(mmu.take_hit().map(|hit| hit.old), Some(0xFF));
+  }
+}
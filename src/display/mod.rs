@@ -6,12 +6,14 @@ mod render_thread;
 
 use super::buffer::Buffer;
 use crate::input::KeyEvent;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 #[allow(dead_code)]
 pub struct Display {
-  render_thread: std::thread::JoinHandle<()>,
+  render_thread: Option<std::thread::JoinHandle<()>>,
   buffer: Arc<Buffer>,
+  closed: Arc<AtomicBool>,
 }
 
 const WIDTH: f64 = 600.0;
@@ -19,11 +21,30 @@ const HEIGHT: f64 = 600.0;
 
 impl Display {
   pub fn new(input_sender: crossbeam_channel::Sender<KeyEvent>, buffer: Arc<Buffer>) -> Display {
-    let render = render_thread::spawn(WIDTH, HEIGHT, input_sender, Arc::clone(&buffer));
+    let closed = Arc::new(AtomicBool::new(false));
+    let render = render_thread::spawn(
+      WIDTH,
+      HEIGHT,
+      input_sender,
+      Arc::clone(&buffer),
+      Arc::clone(&closed),
+    );
 
     Display {
-      render_thread: render,
+      render_thread: Some(render),
       buffer: buffer,
+      closed,
+    }
+  }
+
+  pub fn closed(&self) -> bool {
+    self.closed.load(Ordering::Relaxed)
+  }
+
+  // blocks until the window is closed
+  pub fn wait(&mut self) {
+    if let Some(render_thread) = self.render_thread.take() {
+      let _ = render_thread.join();
     }
   }
 }
@@ -6,6 +6,7 @@ extern crate texture;
 use crossbeam_channel::Sender;
 use piston::{Button, Event};
 use piston_window::{PistonWindow, Texture};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{sync::Arc, thread};
 
 use crate::buffer::Buffer;
@@ -16,6 +17,7 @@ pub fn spawn(
   height: f64,
   input_sender: Sender<KeyEvent>,
   buffer: Arc<Buffer>,
+  closed: Arc<AtomicBool>,
 ) -> thread::JoinHandle<()> {
   use piston::window::WindowSettings;
 
@@ -25,6 +27,7 @@ pub fn spawn(
       .unwrap();
 
     render_loop(window, input_sender, buffer);
+    closed.store(true, Ordering::Relaxed);
   })
 }
 
@@ -0,0 +1,205 @@
+// entry points of the fuzz targets in fuzz/, kept in the crate so that the
+// unit tests run them too
+//
+// they panic when an invariant breaks, which is what the fuzzer looks for.
+// cargo-fuzz builds with overflow checks, so arithmetic overflowing
+// anywhere panics as well
+
+use crate::buffer::Buffer;
+use crate::bus::Bus;
+use crate::cpu::opcodes::{self, Arg, Opcode};
+use crate::cpu::snapshot::{self, Snapshot};
+use crate::cpu::{CpuError, CPU};
+use crate::gpu::GPU;
+use crate::mmu::addrs::Addr;
+use crate::mmu::{real_mmu::RealMMU, test_mmu::TestMMU, MMU};
+use crate::scheduler::{Event, Scheduler};
+use std::sync::Arc;
+
+// instructions executed at most per input
+const PROGRAM_STEPS: usize = 64;
+const ROM_STEPS: usize = 10_000;
+
+// the input is an encoded CPU snapshot without its version byte, setting
+// the registers and state, followed by a program loaded at PC
+pub fn exec_program(data: &[u8]) {
+  let state_size = snapshot::ENCODED_SIZE - 1;
+
+  if data.len() < state_size {
+    return;
+  }
+
+  let mut encoded = vec![snapshot::VERSION];
+  encoded.extend_from_slice(&data[..state_size]);
+  let snapshot = Snapshot::decode(&encoded).unwrap();
+
+  let mut cpu = CPU::new();
+  cpu.restore(&snapshot);
+
+  let mut mmu = TestMMU::new();
+
+  for (i, &byte) in data[state_size..].iter().enumerate() {
+    mmu.write8(snapshot.pc.wrapping_add(i as u16), byte);
+  }
+
+  for _ in 0..PROGRAM_STEPS {
+    if exec_checked(&mut cpu, &mut mmu).is_err() {
+      break;
+    }
+  }
+}
+
+// the input is a cartridge image, run from its entry point as the boot ROM
+// leaves it
+pub fn run_rom(data: &[u8]) {
+  let mut mmu = RealMMU::new(false, data.to_vec());
+  let mut gpu = GPU::new(Arc::new(Buffer::from_size(160, 144)));
+  let mut scheduler = Scheduler::new();
+  scheduler.schedule(gpu.cycles_to_event() as u64, Event::Ppu);
+
+  let mut cpu = CPU::new();
+  let mut state = cpu.snapshot();
+  state.pc = 0x0100;
+  state.sp = 0xFFFE;
+  cpu.restore(&state);
+
+  let mut bus = Bus::new(&mut mmu, &mut gpu, &mut scheduler);
+
+  for _ in 0..ROM_STEPS {
+    if exec_checked(&mut cpu, &mut bus).is_err() {
+      break;
+    }
+  }
+}
+
+// executes the next instruction, checking that PC either moved past it or
+// went where the instruction jumps to. errors are fine, as long as the
+// CPU reports them instead of panicking
+fn exec_checked<M: MMU>(cpu: &mut CPU, mmu: &mut M) -> Result<(), CpuError> {
+  let before = cpu.snapshot();
+  let pc = before.pc;
+  let opcode = opcodes::decode(peek(mmu, pc));
+  let next = pc.wrapping_add(opcodes::op_size(opcode));
+  let target = jump_target(opcode, pc, cpu, mmu);
+
+  // no instruction runs while the CPU is waiting, and whichever does on
+  // the way out of the HALT bug or into an interrupt starts elsewhere
+  let pending = peek(mmu, Addr::InterruptFlag as u16) & peek(mmu, Addr::InterruptEnable as u16);
+  let runs = !(before.halted
+    || before.stopped
+    || before.locked
+    || before.halt_bug
+    || before.ime && pending & 0x1F != 0);
+
+  cpu.exec(mmu)?;
+
+  if !runs {
+    return Ok(());
+  }
+
+  let new_pc = cpu.regs().pc();
+  let valid = match opcode {
+    // locks up in place
+    Opcode::ILLEGAL(_) => new_pc == pc,
+    // anywhere at all, from the stack
+    Opcode::RET(_) | Opcode::RETI => true,
+    // always taken
+    Opcode::RST(_) => Some(new_pc) == target,
+    Opcode::JUMP(..) | Opcode::CALL(..) => new_pc == next || Some(new_pc) == target,
+    _ => new_pc == next,
+  };
+
+  assert!(
+    valid,
+    "PC went from {:#06x} to {:#06x} executing {:?}",
+    pc, new_pc, opcode
+  );
+
+  Ok(())
+}
+
+// where a jump, call or restart goes when taken
+fn jump_target<M: MMU>(opcode: Opcode, pc: u16, cpu: &CPU, mmu: &M) -> Option<u16> {
+  let imm8 = peek(mmu, pc.wrapping_add(1));
+  let imm16 = u16::from_le_bytes([imm8, peek(mmu, pc.wrapping_add(2))]);
+
+  match opcode {
+    Opcode::JUMP(_, Arg::Imm8) => Some(pc.wrapping_add(2).wrapping_add(imm8 as i8 as u16)),
+    Opcode::JUMP(_, Arg::Addr16) | Opcode::CALL(_, Arg::Addr16) => Some(imm16),
+    Opcode::JUMP(_, Arg::Reg16(reg16)) => Some(cpu.regs().read16(reg16)),
+    Opcode::RST(n) => Some((n as u16) << 3),
+    _ => None,
+  }
+}
+
+// reads without side effects, with unmapped memory floating high
+fn peek<M: MMU>(mmu: &M, address: u16) -> u8 {
+  mmu.peek8(address).unwrap_or(0xFF)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a small xorshift generator, so that the inputs are the same every run
+  fn random_bytes(seed: &mut u32, len: usize) -> Vec<u8> {
+    (0..len)
+      .map(|_| {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        *seed as u8
+      })
+      .collect()
+  }
+
+  fn state(pc: u16, sp: u16) -> Vec<u8> {
+    let mut state = CPU::new().snapshot();
+    state.pc = pc;
+    state.sp = sp;
+    state.af = 0xFFF0;
+
+    state.encode()[1..].to_vec()
+  }
+
+  #[test]
+  fn every_opcode_at_the_edges() {
+    // operands jumping as far back and forth as they can
+    let operands = [[0x00, 0x00], [0x7F, 0xFF], [0x80, 0x00], [0xFF, 0xFF]];
+
+    for &pc in &[0x0000, 0x0001, 0xFFFD, 0xFFFE, 0xFFFF] {
+      for &sp in &[0x0000, 0x0001, 0xFFFF] {
+        for byte in 0..=0xFFu8 {
+          for operand in &operands {
+            let mut data = state(pc, sp);
+            data.extend_from_slice(&[byte, operand[0], operand[1]]);
+
+            exec_program(&data);
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn random_programs() {
+    let mut seed = 0x2545_F491;
+
+    for _ in 0..2000 {
+      exec_program(&random_bytes(&mut seed, 48));
+    }
+  }
+
+  #[test]
+  fn malformed_roms() {
+    let mut seed = 0x9E37_79B9;
+
+    exec_program(&[]);
+    run_rom(&[]);
+    run_rom(&[0x18]);
+
+    for len in &[0x0100, 0x0150, 0x4001, 0x8000] {
+      run_rom(&random_bytes(&mut seed, *len));
+    }
+  }
+}
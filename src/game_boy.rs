@@ -1,16 +1,45 @@
 extern crate crossbeam_channel;
 
-use super::mmu::real_mmu::RealMMU;
+use super::bus::Bus;
+use super::cpu::{journal::Journal, profiler::Profiler, trace::Tracer, CpuError};
+use super::debug::breakpoints::{Breakpoint, Breakpoints};
+use super::debug::cdl;
+use super::debug::symbols::Symbols;
+use super::debug::watchpoints::{Hit, Watched, Watchpoints};
+use super::mmu::{real_mmu::RealMMU, MMU};
+use super::scheduler::{self, Scheduler};
 use super::{buffer::Buffer, cpu::CPU, display::Display, gpu::GPU, input::Input};
+use std::io;
 use std::sync::Arc;
 
+// cartridge header byte signaling CGB support
+const CGB_FLAG: usize = 0x0143;
+
+// why `run` gave control back to its caller
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+  Breakpoint(Breakpoint),
+  Watchpoint(Hit),
+  Crash(CpuError),
+  Closed,
+}
+
 #[allow(dead_code)]
 pub struct GameBoy {
   cpu: CPU,
   gpu: GPU,
   mmu: RealMMU,
+  scheduler: Scheduler,
   display: Display,
   input: Input,
+  breakpoints: Breakpoints,
+  watchpoints: Watchpoints,
+  symbols: Option<Symbols>,
+  // PC of the breakpoint the last run stopped at, which the next one
+  // resumes from without stopping there again
+  breakpoint_pc: Option<u16>,
+  skip_idle_loops: bool,
+  cache_blocks: bool,
 }
 
 impl GameBoy {
@@ -19,29 +48,231 @@ impl GameBoy {
     let (input_sender, input_receiver) = crossbeam_channel::unbounded();
 
     let cartridge = std::fs::read(cartridge_path).unwrap();
+    let symbols = Symbols::try_load_for_cartridge(cartridge_path);
+    let cgb_mode = cartridge
+      .get(CGB_FLAG)
+      .map_or(false, |flag| flag & 0x80 > 0);
     let buffer = Arc::new(Buffer::from_size(160, 144));
     let mmu = RealMMU::new(true, cartridge);
 
-    let cpu = CPU::new();
+    let mut cpu = CPU::new();
+    cpu.cgb_mode = cgb_mode;
 
     let gpu = GPU::new(Arc::clone(&buffer));
 
+    let mut scheduler = Scheduler::new();
+    scheduler.schedule(gpu.cycles_to_event() as u64, scheduler::Event::Ppu);
+
     let display = Display::new(input_sender, Arc::clone(&buffer));
-    let input = Input::new(input_receiver);
+    let input = Input::new(input_receiver, mmu.buttons());
 
     GameBoy {
       cpu,
       mmu,
       gpu,
+      scheduler,
       input,
       display,
+      breakpoints: Breakpoints::new(),
+      watchpoints: Watchpoints::new(),
+      symbols,
+      breakpoint_pc: None,
+      skip_idle_loops: false,
+      cache_blocks: false,
     }
   }
 
-  pub fn run(&mut self) {
+  // with `labeled`, traces are labeled with the cartridge's symbols, if it
+  // has any, at the cost of no longer being in the Gameboy Doctor format
+  pub fn set_tracer(&mut self, tracer: Tracer, labeled: bool) {
+    let tracer = match &self.symbols {
+      Some(symbols) if labeled => tracer.with_symbols(symbols.clone()),
+      _ => tracer,
+    };
+
+    self.cpu.set_tracer(tracer);
+  }
+
+  pub fn set_profiler(&mut self, profiler: Profiler) {
+    self.cpu.set_profiler(profiler);
+  }
+
+  pub fn take_profiler(&mut self) -> Option<Profiler> {
+    self.cpu.take_profiler()
+  }
+
+  // maps every cartridge byte executed or read from now on
+  pub fn log_code_data(&mut self) {
+    let logger = cdl::Logger::new(self.mmu.rom_size());
+    self.cpu.set_code_data_logger(logger);
+  }
+
+  pub fn take_code_data_logger(&mut self) -> Option<cdl::Logger> {
+    self.cpu.take_code_data_logger()
+  }
+
+  // fast-forwards through loops polling memory, unless breakpoints or
+  // watchpoints need to see every instruction
+  pub fn skip_idle_loops(&mut self) {
+    self.skip_idle_loops = true;
+  }
+
+  // runs from cached basic blocks, unless watchpoints need to see the
+  // instruction bytes being read
+  pub fn cache_blocks(&mut self) {
+    self.cache_blocks = true;
+  }
+
+  // clock cycles since power on
+  #[allow(dead_code)]
+  pub fn cycles(&self) -> u64 {
+    self.scheduler.now()
+  }
+
+  // journals the last `capacity` instructions from now on, to step back with
+  #[allow(dead_code)]
+  pub fn record_journal(&mut self, capacity: usize) {
+    self.cpu.set_journal(Journal::new(capacity));
+  }
+
+  // undoes the last instruction executed, returning whether it was journaled
+  #[allow(dead_code)]
+  pub fn step_back(&mut self) -> bool {
+    self.cpu.step_back(&mut self.mmu)
+  }
+
+  // goes back to the instruction that last wrote to the address,
+  // returning whether it was journaled
+  #[allow(dead_code)]
+  pub fn step_back_to_write(&mut self, address: u16) -> bool {
+    self.cpu.step_back_to_write(&mut self.mmu, address)
+  }
+
+  #[allow(dead_code)]
+  pub fn breakpoints(&mut self) -> &mut Breakpoints {
+    &mut self.breakpoints
+  }
+
+  // sets a breakpoint on a label of the cartridge's symbols,
+  // returning whether there is such a label
+  #[allow(dead_code)]
+  pub fn break_at_label(&mut self, name: &str) -> bool {
+    match self
+      .symbols
+      .as_ref()
+      .and_then(|symbols| symbols.breakpoint(name))
+    {
+      Some(breakpoint) => {
+        self.breakpoints.add(breakpoint);
+        true
+      }
+      None => false,
+    }
+  }
+
+  #[allow(dead_code)]
+  pub fn watchpoints(&mut self) -> &mut Watchpoints {
+    &mut self.watchpoints
+  }
+
+  // runs until a breakpoint or watchpoint is hit, the CPU crashes or the
+  // window is closed. running again after a breakpoint resumes from it
+  pub fn run(&mut self) -> Stop {
+    let stop = self.run_until_stop();
+    // the trace should be complete up to whatever stopped the run
+    self.cpu.flush_tracer();
+
+    stop
+  }
+
+  fn run_until_stop(&mut self) -> Stop {
+    let mut resume_from = self.breakpoint_pc.take();
+
+    self.cpu.set_idle_loop_detection(
+      self.skip_idle_loops && self.breakpoints.is_empty() && self.watchpoints.is_empty(),
+    );
+    self
+      .cpu
+      .set_block_cache(self.cache_blocks && self.watchpoints.is_empty());
+
     loop {
-      self.cpu.exec(&mut self.mmu);
-      self.gpu.step(self.cpu.last_instr_cycles, &mut self.mmu);
+      if self.display.closed() {
+        return Stop::Closed;
+      }
+
+      let pc = self.cpu.regs().pc();
+
+      if resume_from.take() != Some(pc) {
+        if let Some(breakpoint) = self.check_breakpoints() {
+          self.breakpoint_pc = Some(pc);
+          return Stop::Breakpoint(breakpoint);
+        }
+      }
+
+      let mut bus = Bus::new(&mut self.mmu, &mut self.gpu, &mut self.scheduler);
+      let mut hit = None;
+
+      let result = if self.watchpoints.is_empty() {
+        self.cpu.exec(&mut bus)
+      } else {
+        let mut watched = Watched::new(bus, &self.watchpoints, self.cpu.regs().pc());
+        let result = self.cpu.exec(&mut watched);
+        hit = watched.take_hit();
+
+        result
+      };
+
+      if let Err(error) = result {
+        return Stop::Crash(error);
+      }
+
+      if let Some(hit) = hit {
+        return Stop::Watchpoint(hit);
+      }
+
+      if let Some(event) = self.cpu.take_event() {
+        eprintln!("{}", event);
+        self.print_backtrace();
+      }
+    }
+  }
+
+  fn check_breakpoints(&self) -> Option<Breakpoint> {
+    if self.breakpoints.is_empty() || self.cpu.idle() {
+      return None;
     }
+
+    let pc = self.cpu.regs().pc();
+    let opcode = if self.breakpoints.has_opcodes() {
+      self.mmu.peek8(pc)
+    } else {
+      None
+    };
+
+    self.breakpoints.check(pc, self.mmu.rom_bank(pc), opcode)
+  }
+
+  // reports the error along with the CPU state, leaving the last frame
+  // on screen until the window is closed
+  pub fn crash(&mut self, error: CpuError) {
+    eprintln!("{}", error);
+    eprintln!("{}", self.cpu.regs());
+    self.print_backtrace();
+
+    self.display.wait();
+  }
+
+  // prints where PC is, followed by every routine and interrupt handler
+  // it's nested in, labeled with the cartridge's symbols
+  pub fn print_backtrace(&self) {
+    let pc = self.cpu.regs().pc();
+    let stderr = io::stderr();
+
+    let _ = self.cpu.call_stack().write_backtrace(
+      &mut stderr.lock(),
+      pc,
+      self.mmu.rom_bank(pc),
+      self.symbols.as_ref(),
+    );
   }
 }
@@ -20,7 +20,7 @@ impl GPU {
     }
   }
 
-  pub fn step<M: MMU>(&mut self, cycles: u8, mmu: &mut M) {
+  pub fn step<M: MMU>(&mut self, cycles: u32, mmu: &mut M) {
     use step::Result::*;
 
     match self.step.calc(cycles, mmu) {
@@ -28,4 +28,10 @@ impl GPU {
       Noop => (),
     }
   }
+
+  // cycles until the current mode ends, which is when anything visible
+  // happens next
+  pub fn cycles_to_event(&self) -> u32 {
+    self.step.remaining()
+  }
 }
@@ -13,8 +13,16 @@ const TILEMAP_1_OFFSET: u32 = 0x1C00;
 
 pub const VRAM_BEG: usize = 0x8000;
 
+const SCREEN_HEIGHT: u32 = 144;
+
 pub fn renderscan<M: MMU>(buffer: &Arc<Buffer>, mmu: &mut M) {
   let line = mmu.read8(Addr::CurrentScanLine) as u32;
+
+  // games can write LY, leaving it past the bottom of the screen
+  if line >= SCREEN_HEIGHT {
+    return;
+  }
+
   let scroll_x = mmu.read8(Addr::ScrollX) as u32;
   let scroll_y = mmu.read8(Addr::ScrollY) as u32;
   let bg_map = mmu.get_flag(Addr::LCDControl, LCDControlReg::BGTileMap);
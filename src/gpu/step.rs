@@ -1,4 +1,4 @@
-use crate::mmu::{addrs::Addr, MMU};
+use crate::mmu::{addrs::Addr, addrs::Interrupt, MMU};
 
 #[derive(Debug, PartialEq)]
 pub enum Mode {
@@ -31,14 +31,14 @@ impl Step {
 
   // inspired in
   // http://imrannazar.com/GameBoy-Emulation-in-JavaScript:-step.Timings
-  pub fn calc<M: MMU>(&mut self, cycles: u8, mmu: &mut M) -> Result {
+  pub fn calc<M: MMU>(&mut self, cycles: u32, mmu: &mut M) -> Result {
     let line = mmu.read8(Addr::CurrentScanLine as usize);
 
-    self.mode_clock = self.mode_clock + cycles as u32;
+    self.mode_clock = self.mode_clock + cycles;
 
     match self.mode {
       ScanlineOAM => {
-        if self.mode_clock >= 80 {
+        if self.mode_clock >= self.duration() {
           self.mode_clock = 0;
           self.mode = ScanlineVRAM;
         }
@@ -46,7 +46,7 @@ impl Step {
         Result::Noop
       }
       ScanlineVRAM => {
-        if self.mode_clock >= 172 {
+        if self.mode_clock >= self.duration() {
           self.mode_clock = 0;
           self.mode = HBlank;
 
@@ -56,12 +56,13 @@ impl Step {
         }
       }
       HBlank => {
-        if self.mode_clock >= 204 {
+        if self.mode_clock >= self.duration() {
           self.mode_clock = 0;
-          mmu.write8(Addr::CurrentScanLine, line + 1);
+          mmu.write8(Addr::CurrentScanLine, line.wrapping_add(1));
 
           if line == 143 {
             self.mode = VBlank;
+            mmu.set_flag(Addr::InterruptFlag, Interrupt::VBlank);
 
           // panic!("render screen");
           } else {
@@ -72,9 +73,9 @@ impl Step {
         Result::Noop
       }
       VBlank => {
-        if self.mode_clock >= 456 {
+        if self.mode_clock >= self.duration() {
           self.mode_clock = 0;
-          mmu.write8(Addr::CurrentScanLine, line + 1);
+          mmu.write8(Addr::CurrentScanLine, line.wrapping_add(1));
 
           if line > 152 {
             self.mode = ScanlineOAM;
@@ -86,6 +87,22 @@ impl Step {
       }
     }
   }
+
+  // cycles until the current mode ends
+  pub fn remaining(&self) -> u32 {
+    self.duration().saturating_sub(self.mode_clock)
+  }
+
+  // in clock cycles, which the PPU keeps to even when the CPU runs at
+  // double speed
+  fn duration(&self) -> u32 {
+    match self.mode {
+      ScanlineOAM => 80,
+      ScanlineVRAM => 172,
+      HBlank => 204,
+      VBlank => 456,
+    }
+  }
 }
 
 #[cfg(test)]
@@ -166,6 +183,20 @@ mod tests {
     assert_eq!(step.mode, VBlank);
     assert_eq!(mmu.read8(Addr::CurrentScanLine), 144);
     assert_eq!(step.mode_clock, 0);
+    assert_eq!(mmu.get_flag(Addr::InterruptFlag, Interrupt::VBlank), true);
+  }
+
+  #[test]
+  fn remaining() {
+    let mut mmu = TestMMU::new();
+    let mut step = Step::new();
+    assert_eq!(step.remaining(), 80);
+
+    step.calc(8, &mut mmu);
+    assert_eq!(step.remaining(), 72);
+
+    step.calc(72, &mut mmu);
+    assert_eq!(step.remaining(), 172);
   }
 
   #[test]
@@ -3,6 +3,8 @@ use std::thread;
 
 use piston::keyboard::Key;
 
+use crate::mmu::joypad::{Button, Buttons};
+
 pub type KeyEvent = (Key, bool);
 
 #[allow(dead_code)]
@@ -11,38 +13,53 @@ pub struct Input {
 }
 
 impl Input {
-  pub fn new(receiver: Receiver<KeyEvent>) -> Input {
-    let thread = thread::spawn(move || receiver_loop(receiver));
+  // presses and releases the buttons the keys are mapped to
+  pub fn new(receiver: Receiver<KeyEvent>, buttons: Buttons) -> Input {
+    let thread = thread::spawn(move || receiver_loop(receiver, buttons));
 
     Input { thread: thread }
   }
 }
 
-fn receiver_loop(receiver: Receiver<KeyEvent>) {
+fn receiver_loop(receiver: Receiver<KeyEvent>, buttons: Buttons) {
   loop {
     let (key, state) = receiver.recv().expect("Failed to receive input event");
 
     if state {
-      handle_key_press(key)
+      handle_key_press(key, &buttons)
     } else {
-      handle_key_release(key)
+      handle_key_release(key, &buttons)
     }
   }
 }
 
-fn handle_key_press(keycode: Key) {
+fn button(keycode: Key) -> Option<Button> {
   match keycode {
-    Key::Escape => std::process::exit(0),
-    Key::Up => println!("UP"),
-    Key::Down => println!("DOWN"),
-    Key::Left => println!("LEFT"),
-    Key::Right => println!("RIGHT"),
-    _ => (),
+    Key::Up => Some(Button::Up),
+    Key::Down => Some(Button::Down),
+    Key::Left => Some(Button::Left),
+    Key::Right => Some(Button::Right),
+    Key::Z => Some(Button::A),
+    Key::X => Some(Button::B),
+    Key::Backspace => Some(Button::Select),
+    Key::Return => Some(Button::Start),
+    _ => None,
   }
 }
 
-fn handle_key_release(keycode: Key) {
+fn handle_key_press(keycode: Key, buttons: &Buttons) {
   match keycode {
-    _ => (),
+    Key::Escape => std::process::exit(0),
+    _ => {
+      if let Some(button) = button(keycode) {
+        buttons.press(button);
+      }
+    }
+  }
+}
+
+fn handle_key_release(keycode: Key, buttons: &Buttons) {
+  if let Some(button) = button(keycode) {
+    buttons.release(button);
   }
 }
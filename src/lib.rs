@@ -0,0 +1,13 @@
+// the emulator core, without the window and input handling, so that it can
+// be driven by other crates such as the fuzz targets in fuzz/
+
+pub mod buffer;
+pub mod bus;
+pub mod cpu;
+pub mod debug;
+pub mod gpu;
+pub mod mmu;
+pub mod scheduler;
+
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
@@ -2,20 +2,89 @@
 extern crate clap;
 extern crate crossbeam_channel;
 
-mod buffer;
-pub mod cpu;
 mod display;
 mod game_boy;
-mod gpu;
 mod input;
-mod mmu;
+
+use rgba::{buffer, bus, cpu, debug, gpu, mmu, scheduler};
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+// entries in each section of the profile report
+const PROFILE_LIMIT: usize = 50;
 
 fn main() {
   let yaml = load_yaml!("../assets/cli.yml");
   let matches = clap::App::from_yaml(yaml).get_matches();
 
+  if let Some(matches) = matches.subcommand_matches("disasm") {
+    return disasm(matches);
+  }
+
   let cartridge_path = matches.value_of("cartridge").unwrap();
   let mut game_boy = game_boy::GameBoy::new(cartridge_path);
 
-  game_boy.run();
+  if let Some(path) = matches.value_of("trace") {
+    let tracer = cpu::trace::Tracer::to_file(path).unwrap();
+    game_boy.set_tracer(tracer, matches.is_present("trace-labels"));
+  }
+
+  if matches.is_present("profile") {
+    game_boy.set_profiler(cpu::profiler::Profiler::new());
+  }
+
+  if matches.is_present("cdl") {
+    game_boy.log_code_data();
+  }
+
+  if matches.is_present("skip-idle-loops") {
+    game_boy.skip_idle_loops();
+  }
+
+  if matches.is_present("cache-blocks") {
+    game_boy.cache_blocks();
+  }
+
+  let stop = game_boy.run();
+
+  if let Some(path) = matches.value_of("profile") {
+    let mut out = BufWriter::new(File::create(path).unwrap());
+    let profiler = game_boy.take_profiler().unwrap();
+
+    profiler.report(&mut out, PROFILE_LIMIT).unwrap();
+  }
+
+  if let Some(path) = matches.value_of("cdl") {
+    let logger = game_boy.take_code_data_logger().unwrap();
+
+    std::fs::write(path, logger.flags()).unwrap();
+  }
+
+  match stop {
+    game_boy::Stop::Crash(error) => game_boy.crash(error),
+    game_boy::Stop::Breakpoint(breakpoint) => {
+      eprintln!("Breakpoint {} hit", breakpoint);
+      game_boy.print_backtrace();
+    }
+    game_boy::Stop::Watchpoint(hit) => {
+      eprintln!("Watchpoint hit: {}", hit);
+      game_boy.print_backtrace();
+    }
+    game_boy::Stop::Closed => (),
+  }
+}
+
+fn disasm(matches: &clap::ArgMatches) {
+  let cartridge_path = matches.value_of("cartridge").unwrap();
+  let cartridge = std::fs::read(cartridge_path).unwrap();
+  let cdl = matches
+    .value_of("cdl")
+    .map(|path| std::fs::read(path).unwrap());
+  let symbols = debug::symbols::Symbols::try_load_for_cartridge(cartridge_path);
+
+  let stdout = io::stdout();
+  let mut out = BufWriter::new(stdout.lock());
+
+  debug::listing::write_listing(&cartridge, cdl.as_deref(), symbols.as_ref(), &mut out).unwrap();
 }
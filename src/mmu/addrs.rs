@@ -1,10 +1,18 @@
 #[derive(Debug)]
 pub enum Addr {
+  Joypad = 0xFF00,
+  Divider = 0xFF04,
+  InterruptFlag = 0xFF0F,
   LCDControl = 0xFF40,
   ScrollY = 0xFF42,
   ScrollX = 0xFF43,
   CurrentScanLine = 0xFF44,
   BGPalette = 0xFF47,
+  SpeedSwitch = 0xFF4D,
+  VRAMBank = 0xFF4F,
+  BootROM = 0xFF50,
+  WRAMBank = 0xFF70,
+  InterruptEnable = 0xFFFF,
 }
 
 pub enum LCDControlReg {
@@ -18,6 +26,15 @@ pub enum LCDControlReg {
   LCDEnabled = 0b1000_0000,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Interrupt {
+  VBlank = 0b0000_0001,
+  LCDStat = 0b0000_0010,
+  Timer = 0b0000_0100,
+  Serial = 0b0000_1000,
+  Joypad = 0b0001_0000,
+}
+
 impl From<Addr> for usize {
   fn from(addr: Addr) -> Self {
     addr as usize
@@ -29,3 +46,9 @@ impl From<LCDControlReg> for u8 {
     addr as u8
   }
 }
+
+impl From<Interrupt> for u8 {
+  fn from(interrupt: Interrupt) -> Self {
+    interrupt as u8
+  }
+}
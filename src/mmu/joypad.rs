@@ -0,0 +1,68 @@
+// buttons held down, shared between the thread handling input and the MMU
+// driving the joypad lines from them
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+// the directions take up the low nibble, the other buttons the high one,
+// in the order of the joypad lines
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Button {
+  Right = 0b0000_0001,
+  Left = 0b0000_0010,
+  Up = 0b0000_0100,
+  Down = 0b0000_1000,
+  A = 0b0001_0000,
+  B = 0b0010_0000,
+  Select = 0b0100_0000,
+  Start = 0b1000_0000,
+}
+
+#[derive(Clone)]
+pub struct Buttons {
+  held: Arc<AtomicU8>,
+}
+
+impl Buttons {
+  pub fn new() -> Buttons {
+    Buttons {
+      held: Arc::new(AtomicU8::new(0)),
+    }
+  }
+
+  pub fn press(&self, button: Button) {
+    self.held.fetch_or(button as u8, Ordering::Relaxed);
+  }
+
+  pub fn release(&self, button: Button) {
+    self.held.fetch_and(!(button as u8), Ordering::Relaxed);
+  }
+
+  pub fn held(&self) -> u8 {
+    self.held.load(Ordering::Relaxed)
+  }
+}
+
+impl Default for Buttons {
+  fn default() -> Buttons {
+    Buttons::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn press_release() {
+    let buttons = Buttons::new();
+    let shared = buttons.clone();
+
+    shared.press(Button::Start);
+    shared.press(Button::Left);
+    assert_eq!(buttons.held(), 0b1000_0010);
+
+    shared.release(Button::Start);
+    assert_eq!(buttons.held(), 0b0000_0010);
+  }
+}
@@ -1,11 +1,19 @@
 pub mod addrs;
+pub mod joypad;
 pub mod real_mmu;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "fuzzing"))]
 pub mod test_mmu;
 
 use std::convert::Into;
 
+// an access to an address that isn't mapped to anything
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Fault {
+  Read(u16),
+  Write(u16),
+}
+
 pub trait MMU {
   fn read8<I>(&self, index: I) -> u8
   where
@@ -41,4 +49,27 @@ pub trait MMU {
   where
     I: Into<usize>,
     U: Into<u8>;
+
+  // advances everything else on the bus by the given clock cycles,
+  // called by the CPU on every memory access and internal delay
+  fn tick(&mut self, _cycles: u8) {}
+
+  // ROM bank mapped at the given address, if it's in ROM
+  fn rom_bank(&self, _address: u16) -> Option<u8> {
+    None
+  }
+
+  // the byte at the address, without raising a fault where it's unmapped
+  fn peek8(&self, _address: u16) -> Option<u8> {
+    None
+  }
+
+  // writes the byte without raising a fault where it's unmapped, such as
+  // to undo a write
+  fn poke8(&mut self, _address: u16, _value: u8) {}
+
+  // takes the first fault since the last call, if any
+  fn take_fault(&mut self) -> Option<Fault> {
+    None
+  }
 }
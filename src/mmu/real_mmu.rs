@@ -1,4 +1,6 @@
-use super::MMU;
+use super::joypad::Buttons;
+use super::{Fault, MMU};
+use std::cell::Cell;
 use std::fs;
 
 // note: memory is little-endian
@@ -80,8 +82,9 @@ const ZRAM_BEG: usize = 0xff80;
 const ZRAM_END: usize = 0xfffe;
 const ZRAM_RANGE: MemRange = (ZRAM_BEG, ZRAM_END);
 
+const JOYPAD: usize = 0xff00;
 const FLAG_BOOT: usize = 0xff50;
-const FLAG_INTERRUPT: usize = 0xffff;
+const INTERRUPT_ENABLE: usize = 0xffff;
 
 macro_rules! declare_mem_bank {
   ($range:ident) => {
@@ -105,7 +108,9 @@ pub struct RealMMU {
   wramx: declare_mem_bank!(WRAMX_RANGE),
   io: declare_mem_bank!(IO_RANGE),
   zram: declare_mem_bank!(ZRAM_RANGE),
-  flag_interrupt: u8,
+  interrupt_enable: u8,
+  buttons: Buttons,
+  fault: Cell<Option<Fault>>,
 }
 
 impl RealMMU {
@@ -126,7 +131,9 @@ impl RealMMU {
       wramx: init_mem_bank!(WRAMX_RANGE),
       io: init_mem_bank!(IO_RANGE),
       zram: init_mem_bank!(ZRAM_RANGE),
-      flag_interrupt: 0u8,
+      interrupt_enable: 0u8,
+      buttons: Buttons::new(),
+      fault: Cell::new(None),
     };
 
     if boot_rom {
@@ -135,6 +142,43 @@ impl RealMMU {
 
     mmu
   }
+
+  pub fn rom_size(&self) -> usize {
+    self.cartridge.len()
+  }
+
+  // the buttons held down, to be pressed from another thread
+  pub fn buttons(&self) -> Buttons {
+    self.buttons.clone()
+  }
+
+  // the button lines read low while a button on a selected line is held
+  fn joypad(&self) -> u8 {
+    let select = self.io[JOYPAD - IO_BEG] & 0x30;
+    let held = self.buttons.held();
+    let mut lines = 0x0F;
+
+    if select & 0x10 == 0 {
+      lines &= !held & 0x0F;
+    }
+
+    if select & 0x20 == 0 {
+      lines &= !(held >> 4);
+    }
+
+    0xC0 | select | lines
+  }
+
+  // keeps only the first fault, as the later ones are usually a consequence
+  fn fault(&self, fault: Fault) {
+    if self.fault.get().is_none() {
+      self.fault.set(Some(fault));
+    }
+  }
+}
+
+fn rom_byte(rom: &[u8], offset: usize) -> u8 {
+  rom.get(offset).cloned().unwrap_or(0xFF)
 }
 
 use std::convert::Into;
@@ -147,18 +191,24 @@ impl MMU for RealMMU {
     let index: usize = idx.into();
 
     match index {
-      BOOT_BEG..=BOOT_END if self.read8(FLAG_BOOT) == 1 => self.boot[index],
+      // images shorter than the region they're mapped to read as open bus
+      BOOT_BEG..=BOOT_END if self.read8(FLAG_BOOT) == 1 => rom_byte(&self.boot, index),
       INTERRUPT_BEG..=INTERRUPT_END if self.read8(FLAG_BOOT) > 0 => self.interrupts[index],
-      ROM0_BEG..=ROM0_END => self.cartridge[index],
-      ROMX_BEG..=ROMX_END => self.cartridge[index - ROMX_BEG],
+      ROM0_BEG..=ROM0_END => rom_byte(&self.cartridge, index),
+      ROMX_BEG..=ROMX_END => rom_byte(&self.cartridge, index - ROMX_BEG),
       ERAM_BEG..=ERAM_END => self.eram[index - ERAM_BEG],
       VRAM_BEG..=VRAM_END => self.vram[index - VRAM_BEG],
       WRAM0_BEG..=WRAM0_END => self.wram0[index - WRAM0_BEG],
       WRAMX_BEG..=WRAMX_END => self.wramx[index - WRAMX_BEG],
       ZRAM_BEG..=ZRAM_END => self.zram[index - ZRAM_BEG],
+      IO_BEG..=IO_END if index == JOYPAD => self.joypad(),
       IO_BEG..=IO_END => self.io[index - IO_BEG],
-      FLAG_INTERRUPT => self.flag_interrupt,
-      _ => panic!("Unsupported MMU read8 to address 0x{:x}", index),
+      INTERRUPT_ENABLE => self.interrupt_enable,
+      // unmapped reads float high
+      _ => {
+        self.fault(Fault::Read(index as u16));
+        0xFF
+      }
     }
   }
   fn read16<I>(&self, idx: I) -> u16
@@ -177,14 +227,14 @@ impl MMU for RealMMU {
     let index: usize = idx.into();
 
     match index {
-      INTERRUPT_BEG..=INTERRUPT_END => self.interrupts[index] = value,
+      INTERRUPT_BEG..=INTERRUPT_END if self.read8(FLAG_BOOT) > 0 => self.interrupts[index] = value,
       VRAM_BEG..=VRAM_END => self.vram[index - VRAM_BEG] = value,
       WRAM0_BEG..=WRAM0_END => self.wram0[index - WRAM0_BEG] = value,
       WRAMX_BEG..=WRAMX_END => self.wramx[index - WRAMX_BEG] = value,
       ZRAM_BEG..=ZRAM_END => self.zram[index - ZRAM_BEG] = value,
       IO_BEG..=IO_END => self.io[index - IO_BEG] = value,
-      FLAG_INTERRUPT => self.flag_interrupt = value,
-      _ => panic!("Unsupported MMU write8 to address {:#06x}", index),
+      INTERRUPT_ENABLE => self.interrupt_enable = value,
+      _ => self.fault(Fault::Write(index as u16)),
     };
   }
 
@@ -205,13 +255,10 @@ impl MMU for RealMMU {
   {
     let address: usize = addr.into();
 
-    let real_address = match address {
-      IO_BEG..=IO_END => address - IO_BEG,
-
-      _ => panic!("Unsupported MMU flag address {:#06x}", address),
-    };
-
-    self.io[real_address] = self.io[real_address] | mask.into();
+    match address {
+      IO_BEG..=IO_END => self.io[address - IO_BEG] |= mask.into(),
+      _ => self.fault(Fault::Write(address as u16)),
+    }
   }
 
   fn unset_flag<I, U>(&mut self, addr: I, mask: U)
@@ -221,13 +268,10 @@ impl MMU for RealMMU {
   {
     let address: usize = addr.into();
 
-    let real_address = match address {
-      IO_BEG..=IO_END => address - IO_BEG,
-
-      _ => panic!("Unsupported MMU flag address {:#06x}", address),
-    };
-
-    self.io[real_address] = self.io[real_address] ^ mask.into();
+    match address {
+      IO_BEG..=IO_END => self.io[address - IO_BEG] &= !mask.into(),
+      _ => self.fault(Fault::Write(address as u16)),
+    }
   }
 
   fn get_flag<I, U>(&self, addr: I, mask: U) -> bool
@@ -237,21 +281,51 @@ impl MMU for RealMMU {
   {
     let address: usize = addr.into();
 
-    let real_address = match address {
-      IO_BEG..=IO_END => address - IO_BEG,
+    match address {
+      IO_BEG..=IO_END => (self.io[address - IO_BEG] & mask.into()) > 0,
+      _ => {
+        self.fault(Fault::Read(address as u16));
+        false
+      }
+    }
+  }
 
-      _ => panic!("Unsupported MMU flag address {:#06x}", address),
-    };
+  // there's no bank switching yet, so ROMX is always bank 1. the boot ROM
+  // isn't part of the cartridge
+  fn rom_bank(&self, address: u16) -> Option<u8> {
+    match address as usize {
+      BOOT_BEG..=BOOT_END if self.read8(FLAG_BOOT) == 1 => None,
+      ROM0_BEG..=ROM0_END => Some(0),
+      ROMX_BEG..=ROMX_END => Some(1),
+      _ => None,
+    }
+  }
 
-    let current = self.io[real_address];
+  fn peek8(&self, address: u16) -> Option<u8> {
+    let fault = self.fault.take();
+    let value = self.read8(address as usize);
 
-    (current & mask.into()) > 0
+    match self.fault.replace(fault) {
+      Some(_) => None,
+      None => Some(value),
+    }
+  }
+
+  fn poke8(&mut self, address: u16, value: u8) {
+    let fault = self.fault.take();
+    self.write8(address as usize, value);
+    self.fault.set(fault);
+  }
+
+  fn take_fault(&mut self) -> Option<Fault> {
+    self.fault.take()
   }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::mmu::joypad::Button;
 
   macro_rules! instantiate_mmu {
     () => {{
@@ -297,19 +371,103 @@ mod tests {
   }
 
   #[test]
-  #[should_panic]
   fn write8_rom0() {
     let mut mmu = instantiate_mmu!();
 
     mmu.write8(ROM0_BEG, 1);
+    assert_eq!(mmu.take_fault(), Some(Fault::Write(ROM0_BEG as u16)));
+  }
+
+  #[test]
+  fn write8_boot_overlay() {
+    let mut mmu = instantiate_mmu!();
+    mmu.set_flag(FLAG_BOOT, 0x2);
+
+    mmu.write8(INTERRUPT_END, 1);
+    assert_eq!(mmu.take_fault(), None);
+    assert_eq!(mmu.read8(INTERRUPT_END), 1);
   }
 
   #[test]
-  #[should_panic]
   fn write8_romx() {
     let mut mmu = instantiate_mmu!();
 
     mmu.write8(ROMX_BEG, 1);
+    assert_eq!(mmu.take_fault(), Some(Fault::Write(ROMX_BEG as u16)));
+    assert_eq!(mmu.read8(ROMX_BEG), 0);
+  }
+
+  #[test]
+  fn rom_bank() {
+    let mmu = instantiate_mmu!();
+
+    assert_eq!(mmu.rom_bank(ROM0_END as u16), Some(0));
+    assert_eq!(mmu.rom_bank(ROMX_BEG as u16), Some(1));
+    assert_eq!(mmu.rom_bank(WRAM0_BEG as u16), None);
+  }
+
+  #[test]
+  fn rom_bank_boot_rom() {
+    let mut mmu = instantiate_mmu!();
+    mmu.set_flag(FLAG_BOOT, 0x1);
+
+    assert_eq!(mmu.rom_bank(BOOT_END as u16), None);
+    assert_eq!(mmu.rom_bank(0x0100), Some(0));
+
+    mmu.unset_flag(FLAG_BOOT, 0x1);
+    assert_eq!(mmu.rom_bank(BOOT_BEG as u16), Some(0));
+  }
+
+  #[test]
+  fn first_fault() {
+    let mut mmu = instantiate_mmu!();
+
+    assert_eq!(mmu.read8(0xfea0usize), 0xFF);
+    mmu.write8(ROMX_END, 1);
+    assert_eq!(mmu.take_fault(), Some(Fault::Read(0xfea0)));
+    assert_eq!(mmu.take_fault(), None);
+  }
+
+  #[test]
+  fn peek8() {
+    let mut mmu = instantiate_mmu!();
+    mmu.write8(WRAM0_BEG, 5);
+
+    assert_eq!(mmu.peek8(WRAM0_BEG as u16), Some(5));
+    assert_eq!(mmu.peek8(0xfea0), None);
+    assert_eq!(mmu.take_fault(), None);
+
+    // faults raised before are kept
+    mmu.read8(0xfea1usize);
+    assert_eq!(mmu.peek8(0xfea0), None);
+    assert_eq!(mmu.take_fault(), Some(Fault::Read(0xfea1)));
+  }
+
+  #[test]
+  fn poke8() {
+    let mut mmu = instantiate_mmu!();
+
+    mmu.poke8(WRAM0_BEG as u16, 5);
+    mmu.poke8(ROM0_BEG as u16, 1);
+    assert_eq!(mmu.read8(WRAM0_BEG), 5);
+    assert_eq!(mmu.read8(ROM0_BEG), 0);
+    assert_eq!(mmu.take_fault(), None);
+  }
+
+  #[test]
+  fn joypad() {
+    let mut mmu = instantiate_mmu!();
+    mmu.buttons().press(Button::Right);
+    mmu.buttons().press(Button::Select);
+
+    mmu.write8(JOYPAD, 0x30);
+    assert_eq!(mmu.read8(JOYPAD), 0xFF);
+
+    mmu.write8(JOYPAD, 0x20);
+    assert_eq!(mmu.read8(JOYPAD), 0xEE);
+
+    mmu.write8(JOYPAD, 0x10);
+    assert_eq!(mmu.read8(JOYPAD), 0xDB);
   }
 
   #[test]
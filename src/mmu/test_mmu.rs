@@ -1,14 +1,25 @@
-use super::MMU;
+use super::{Fault, MMU};
 use std::collections::HashMap;
 
 pub struct TestMMU {
   mem: HashMap<usize, u8>,
+  // clock cycles ticked so far, and the cycle at which each write happened
+  pub cycles: u32,
+  pub writes: Vec<(u32, usize)>,
+  // reported on the next access check, as if the last one were unmapped
+  pub fault: Option<Fault>,
+  // called after every tick, standing in for the rest of the system
+  pub on_tick: Option<fn(&mut TestMMU)>,
 }
 
 impl TestMMU {
   pub fn new() -> TestMMU {
     TestMMU {
       mem: HashMap::new(),
+      cycles: 0,
+      writes: Vec::new(),
+      fault: None,
+      on_tick: None,
     }
   }
 }
@@ -41,6 +52,7 @@ impl MMU for TestMMU {
   {
     let index: usize = idx.into();
 
+    self.writes.push((self.cycles, index));
     self.mem.insert(index, value);
   }
 
@@ -71,7 +83,7 @@ impl MMU for TestMMU {
   {
     let address: usize = addr.into();
 
-    self.mem.insert(address, self.read8(address) ^ mask.into());
+    self.mem.insert(address, self.read8(address) & !mask.into());
   }
 
   fn get_flag<I, U>(&self, addr: I, mask: U) -> bool
@@ -81,6 +93,35 @@ impl MMU for TestMMU {
   {
     (self.read8(addr.into()) & mask.into()) > 0
   }
+
+  fn tick(&mut self, cycles: u8) {
+    self.cycles += cycles as u32;
+
+    if let Some(on_tick) = self.on_tick {
+      on_tick(self);
+    }
+  }
+
+  // ROM without bank switching, like a 32KB cartridge
+  fn rom_bank(&self, address: u16) -> Option<u8> {
+    if address < 0x8000 {
+      Some((address / 0x4000) as u8)
+    } else {
+      None
+    }
+  }
+
+  fn peek8(&self, address: u16) -> Option<u8> {
+    Some(self.read8(address as usize))
+  }
+
+  fn poke8(&mut self, address: u16, value: u8) {
+    self.mem.insert(address as usize, value);
+  }
+
+  fn take_fault(&mut self) -> Option<Fault> {
+    self.fault.take()
+  }
 }
 
 #[cfg(test)]
@@ -0,0 +1,75 @@
+// global clock, and the points in time at which components have
+// something to do, so that they're only run when they do instead of
+// on every cycle
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Event {
+  // the end of the current PPU mode
+  Ppu,
+}
+
+pub struct Scheduler {
+  // clock cycles since power on
+  now: u64,
+  events: BinaryHeap<Reverse<(u64, Event)>>,
+}
+
+impl Scheduler {
+  pub fn new() -> Scheduler {
+    Scheduler {
+      now: 0,
+      events: BinaryHeap::new(),
+    }
+  }
+
+  pub fn now(&self) -> u64 {
+    self.now
+  }
+
+  pub fn schedule(&mut self, at: u64, event: Event) {
+    self.events.push(Reverse((at, event)));
+  }
+
+  pub fn advance(&mut self, cycles: u8) {
+    self.now += cycles as u64;
+  }
+
+  // takes the earliest event due by now, along with the time it was due at
+  pub fn pop_due(&mut self) -> Option<(u64, Event)> {
+    match self.events.peek() {
+      Some(&Reverse((at, _))) if at <= self.now => self.events.pop().map(|Reverse(due)| due),
+      _ => None,
+    }
+  }
+}
+
+impl Default for Scheduler {
+  fn default() -> Scheduler {
+    Scheduler::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pop_due() {
+    let mut scheduler = Scheduler::new();
+    scheduler.schedule(80, Event::Ppu);
+    scheduler.schedule(40, Event::Ppu);
+
+    scheduler.advance(36);
+    assert_eq!(scheduler.pop_due(), None);
+
+    scheduler.advance(200);
+    scheduler.advance(200);
+    assert_eq!(scheduler.now(), 436);
+    assert_eq!(scheduler.pop_due(), Some((40, Event::Ppu)));
+    assert_eq!(scheduler.pop_due(), Some((80, Event::Ppu)));
+    assert_eq!(scheduler.pop_due(), None);
+  }
+}